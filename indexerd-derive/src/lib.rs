@@ -9,15 +9,16 @@ use proc_macro::TokenStream;
 use syn::Ident;
 use syn::Ty;
 use syn::VariantData;
+use syn::{Lit, MetaItem, NestedMetaItem};
 
-#[proc_macro_derive(MysqlObject)]
-pub fn mysql_object(input: TokenStream) -> TokenStream {
+#[proc_macro_derive(DbObject, attributes(fk))]
+pub fn db_object(input: TokenStream) -> TokenStream {
     // Construct a string representation of the type definition
     let s = input.to_string();
     // Parse the string representation
     let ast = syn::parse_derive_input(&s).unwrap();
     // Build the impl
-    let gen = mysql_object_impl(&ast);
+    let gen = db_object_impl(&ast);
     // Return the generated impl
     gen.parse().unwrap()
 }
@@ -25,9 +26,44 @@ pub fn mysql_object(input: TokenStream) -> TokenStream {
 struct FieldInfo {
     pub name: Option<Ident>,
     pub t: Ty,
+    pub fk: Option<(quote::Tokens, quote::Tokens)>, // ForeignKey and the referenced (table, id)
 }
 
-fn mysql_object_impl(ast: &syn::DeriveInput) -> quote::Tokens {
+// #[fk(table = "package")] or #[fk(table_from = "object_type")] for polymorphic references
+fn foreign_key(field: &syn::Field) -> Option<(quote::Tokens, quote::Tokens)> {
+    let ident = field.ident.clone().unwrap();
+    let field_name = ident.to_string();
+    for attr in field.attrs.iter() {
+        let items = match attr.value {
+            MetaItem::List(ref name, ref items) if name == "fk" => items,
+            _ => continue,
+        };
+        for item in items.iter() {
+            match *item {
+                NestedMetaItem::MetaItem(MetaItem::NameValue(ref key, Lit::Str(ref value, _))) => {
+                    return match key.as_ref() {
+                        "table" => Some((
+                            quote!(ForeignKey { field: #field_name, target: FkTarget::Table(#value) },),
+                            quote!((#value, self.#ident),),
+                        )),
+                        "table_from" => {
+                            let table_field = Ident::new(value.as_str());
+                            Some((
+                                quote!(ForeignKey { field: #field_name, target: FkTarget::TableFrom(#value) },),
+                                quote!((self.#table_field.as_str(), self.#ident),),
+                            ))
+                        }
+                        _ => panic!("unknown fk option '{}' on {}", key, field_name),
+                    }
+                }
+                _ => panic!("fk expects table = \"..\" or table_from = \"..\" on {}", field_name),
+            }
+        }
+    }
+    None
+}
+
+fn db_object_impl(ast: &syn::DeriveInput) -> quote::Tokens {
     let class_name = &ast.ident;
     let table_name = to_snake_case(&class_name.to_string());
 
@@ -38,13 +74,22 @@ fn mysql_object_impl(ast: &syn::DeriveInput) -> quote::Tokens {
                 .map(|x| FieldInfo {
                     name: x.ident.clone(),
                     t: x.ty.clone(),
+                    fk: foreign_key(x),
                 })
                 .collect::<Vec<_>>(),
-            _ => panic!("MysqlObject can only be derived for structs"),
+            _ => panic!("DbObject can only be derived for structs"),
         },
-        _ => panic!("MysqlObject can only be derived for structs"),
+        _ => panic!("DbObject can only be derived for structs"),
     };
 
+    let text_init = field_name_type
+        .iter()
+        .map(|x| {
+            let (name, ty) = (x.name.clone(), &x.t);
+            quote!(#name: convert_text::<#ty>(values, mapping, stringify!(#name)),)
+        })
+        .collect::<Vec<_>>();
+
     let member_init = field_name_type
         .iter()
         .map(|x| {
@@ -53,14 +98,43 @@ fn mysql_object_impl(ast: &syn::DeriveInput) -> quote::Tokens {
         })
         .collect::<Vec<_>>();
 
+    let field_names = field_name_type
+        .iter()
+        .map(|x| x.name.clone().unwrap().to_string())
+        .collect::<Vec<_>>();
+
+    let foreign_keys = field_name_type
+        .iter()
+        .filter_map(|x| x.fk.clone().map(|fk| fk.0))
+        .collect::<Vec<_>>();
+
+    let references = field_name_type
+        .iter()
+        .filter_map(|x| x.fk.clone().map(|fk| fk.1))
+        .collect::<Vec<_>>();
+
     quote! {
-        impl MysqlObject for #class_name {
+        impl DbObject for #class_name {
 
             fn table<'life>() -> &'life str
             where Self: Sized {
                 &#table_name
             }
 
+            fn fields() -> &'static [&'static str]
+            where Self: Sized {
+                &[#(#field_names),*]
+            }
+
+            fn foreign_keys() -> &'static [ForeignKey]
+            where Self: Sized {
+                &[#(#foreign_keys)*]
+            }
+
+            fn references(&self) -> Vec<(&str, IdType)> {
+                vec![#(#references)*]
+            }
+
             fn from_slave(row_data: &mysql_cdc::events::row_events::row_data::RowData, fields_map: &HashMap<String, FieldMapping>) -> Self
             where Self: Sized {
                 // #class_name::default()
@@ -73,6 +147,13 @@ fn mysql_object_impl(ast: &syn::DeriveInput) -> quote::Tokens {
                 };
                 obj
             }
+
+            fn from_text(values: &[Option<String>], mapping: &FieldMapping) -> Self
+            where Self: Sized {
+                #class_name {
+                    #(#text_init)*
+                }
+            }
         }
     }
 }
@@ -91,12 +172,23 @@ pub fn storable_raw(input: TokenStream) -> TokenStream {
 
 fn storable_raw_impl(ast: &syn::DeriveInput) -> quote::Tokens {
     let class_name = &ast.ident;
+    let field_names = match ast.body {
+        syn::Body::Struct(VariantData::Struct(ref fields)) => fields
+            .iter()
+            .map(|x| x.ident.clone().unwrap())
+            .collect::<Vec<_>>(),
+        _ => panic!("StorableRaw can only be derived for structs"),
+    };
 
     quote! {
         impl StorableRaw for #class_name {
             fn get_id(&self) -> IdType {
                 self.id
             }
+
+            fn heap_size(&self) -> usize {
+                0 #(+ HeapSize::heap_size(&self.#field_names))*
+            }
         }
     }
 }
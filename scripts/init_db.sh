@@ -24,8 +24,8 @@ mysql_exec="${mysql_exec} ${db_name} -e"
 # create tables
 
 # campaign
-${mysql_exec} "DROP TABLE IF EXISTS campaign; CREATE TABLE campaign (id int, name varchar(255), package_id int);"
-${mysql_exec} "INSERT INTO campaign (id, name, package_id) VALUES (1, 'cmp_1', 2), (2, 'cmp_2', 3);"
+${mysql_exec} "DROP TABLE IF EXISTS campaign; CREATE TABLE campaign (id int, name varchar(255), package_id int, status varchar(32), flight_start_ts int, flight_end_ts int);"
+${mysql_exec} "INSERT INTO campaign (id, name, package_id, status, flight_start_ts, flight_end_ts) VALUES (1, 'cmp_1', 2, 'active', 0, 0), (2, 'cmp_2', 3, 'active', 0, 0);"
 ${mysql_exec} "UPDATE campaign SET package_id=3 where id = 1;"
 ${mysql_exec} "DELETE from campaign where id = 1;"
 
@@ -43,4 +43,4 @@ ${mysql_exec} "CREATE TABLE pad_relation (id int, pad_id int, parent_pad_id int)
 
 # targeting_pad
 ${mysql_exec} "DROP TABLE IF EXISTS targeting_pad;"
-${mysql_exec} "CREATE TABLE targeting_pad (id int, object_id int, object_type varchar(255), positive bool);"
+${mysql_exec} "CREATE TABLE targeting_pad (id int, object_id int, object_type varchar(255), pad_id int, positive bool);"
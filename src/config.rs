@@ -1,24 +1,213 @@
-use serde::Deserialize;
-use serde_json::Result;
+use crate::helpers::CpuTopology;
+use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
-use std::io::Read;
+use std::io::{Read, Result};
 
 #[derive(Default, Debug, Deserialize, Clone, Eq, PartialEq)]
 pub struct Server {
     pub service: Service,
     pub engine: Engine,
     pub updater: Updater,
+    #[serde(default)]
+    pub admin_actions: AdminActions,
+}
+
+#[derive(Default, Debug, Deserialize, Clone, Eq, PartialEq)]
+pub struct AdminActions {
+    // bearer token for POST /admin/actions/*, actions are disabled without it
+    #[serde(default)]
+    pub token: Option<String>,
 }
 
 #[derive(Default, Debug, Deserialize, Clone, Eq, PartialEq)]
 pub struct Service {
     pub listening_port: u16,
+    #[serde(default = "default_bind_hosts")]
+    pub bind_hosts: Vec<String>, // ipv4 or ipv6, each gets its own listener
+    #[serde(default = "default_acceptor_threads")]
+    pub acceptor_threads: usize, // per listener
+    #[serde(default)]
+    pub unix_socket: Option<String>,
+    #[serde(default = "default_queue_size")]
+    pub task_queue_size: usize,
+    #[serde(default = "default_request_timeout_ms")]
+    pub request_timeout_ms: u64, // 0 means no deadline
+    #[serde(default = "default_retry_after_sec")]
+    pub retry_after_sec: u64,
+    #[serde(default)]
+    pub admin: Option<AdminService>, // serve /admin on a separate port and worker
+    #[serde(default)]
+    pub templates_dir: Option<String>, // opt-in for template work: read from here, reload on change
+}
+
+#[derive(Default, Debug, Deserialize, Clone, Eq, PartialEq)]
+pub struct AdminService {
+    pub listening_port: u16,
+    #[serde(default = "default_bind_hosts")]
+    pub bind_hosts: Vec<String>,
+    #[serde(default = "default_admin_queue_size")]
+    pub task_queue_size: usize,
+}
+
+fn default_bind_hosts() -> Vec<String> {
+    vec![String::from("127.0.0.1")]
+}
+
+fn default_acceptor_threads() -> usize {
+    1
+}
+
+fn default_queue_size() -> usize {
+    1000
+}
+
+fn default_admin_queue_size() -> usize {
+    16
+}
+
+fn default_request_timeout_ms() -> u64 {
+    1000
+}
+
+fn default_retry_after_sec() -> u64 {
+    1
 }
 
 #[derive(Default, Debug, Deserialize, Clone, Eq, PartialEq)]
 pub struct Updater {
-    pub db: DB,
+    #[serde(default)]
+    pub db: DB, // used by the mysql source
+    #[serde(default)]
+    pub server_id: u32, // replica id of the mysql source, 0 means the default one
+    #[serde(default)]
+    pub source: Source,
+    #[serde(default)]
+    pub sources: Vec<NamedSource>, // replaces db and source when set
     pub swap_interval: u64,
+    #[serde(default = "default_store_switch_timeout_ms")]
+    pub store_switch_timeout_ms: u64,
+    #[serde(default)]
+    pub history: History,
+    #[serde(default)]
+    pub snapshot: Option<Snapshot>, // warm start from disk instead of a full select
+    #[serde(default)]
+    pub mapped_dir: Option<String>, // publish stores as mmap-ed files here, owned by this instance
+    #[serde(default)]
+    pub memory_limit_mb: u64, // estimated heap of both stores, not ready above it; 0 means no limit
+    #[serde(default)]
+    pub load: Load,
+}
+
+// replica ids of mysql sources without one are counted down from it
+const DEFAULT_SERVER_ID: u32 = 65535;
+
+// one of several sources feeding the same store, each table comes from one source only
+#[derive(Default, Debug, Deserialize, Clone, Eq, PartialEq)]
+pub struct NamedSource {
+    pub name: String,
+    #[serde(default)]
+    pub db: DB, // used by the mysql source
+    #[serde(default)]
+    pub server_id: u32, // replica id of the mysql source, 0 derives one from its position
+    #[serde(default)]
+    pub source: Source,
+    #[serde(default)]
+    pub tables: Vec<String>, // empty means every table
+}
+
+// where objects and their changes come from
+#[derive(Default, Debug, Deserialize, Clone, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    #[default]
+    Mysql,
+    File(FileSource),
+    Postgres(PgSource),
+}
+
+// logical replication through a pgoutput slot, created on the first start; tables with
+// large (toasted) text values need REPLICA IDENTITY FULL for updates to carry them
+#[derive(Default, Debug, Deserialize, Clone, Eq, PartialEq)]
+pub struct PgSource {
+    pub db: DB,
+    pub slot: String,
+    pub publication: String, // CREATE PUBLICATION .. FOR TABLE of the indexed tables
+}
+
+// jsonl files, see data::file_source for the line formats
+#[derive(Default, Debug, Deserialize, Clone, Eq, PartialEq)]
+pub struct FileSource {
+    pub snapshot: String,
+    pub events: String, // read on as it grows, like a binlog
+    #[serde(default)]
+    pub paced: bool, // wait between events as their ts say, as fast as possible otherwise
+}
+
+#[derive(Default, Debug, Deserialize, Clone, Eq, PartialEq)]
+pub struct Snapshot {
+    pub path: String,
+    #[serde(default = "default_snapshot_interval")]
+    pub interval: u64, // seconds between writes by cron, also written on shutdown
+}
+
+fn default_snapshot_interval() -> u64 {
+    600
+}
+
+fn default_store_switch_timeout_ms() -> u64 {
+    1000
+}
+
+// loads from the sources: initial, full reload and consistency check
+#[derive(Debug, Deserialize, Clone, Eq, PartialEq)]
+pub struct Load {
+    #[serde(default = "default_load_threads")]
+    pub threads: usize, // tables loaded at once, each on its own connection
+    #[serde(default = "default_load_chunk_size")]
+    pub chunk_size: usize, // rows per select, paginated by id
+}
+
+impl Default for Load {
+    fn default() -> Self {
+        Load {
+            threads: default_load_threads(),
+            chunk_size: default_load_chunk_size(),
+        }
+    }
+}
+
+fn default_load_threads() -> usize {
+    4
+}
+
+fn default_load_chunk_size() -> usize {
+    10000
+}
+
+// per-object change history shown in admin
+#[derive(Debug, Deserialize, Clone, Eq, PartialEq)]
+pub struct History {
+    #[serde(default = "default_history_depth")]
+    pub depth: usize, // changes kept per object, 0 disables history
+    #[serde(default = "default_history_max_objects")]
+    pub max_objects: usize, // least recently changed objects are forgotten first
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History {
+            depth: default_history_depth(),
+            max_objects: default_history_max_objects(),
+        }
+    }
+}
+
+fn default_history_depth() -> usize {
+    16
+}
+
+fn default_history_max_objects() -> usize {
+    100000
 }
 
 #[derive(Default, Debug, Deserialize, Clone, Eq, PartialEq)]
@@ -33,7 +222,29 @@ pub struct DB {
 #[derive(Default, Debug, Deserialize, Clone, Eq, PartialEq)]
 pub struct Engine {
     pub worker: Worker,
-    pub non_worker_cores: HashSet<u16>,
+    #[serde(default)]
+    pub workers_count: usize, // 0 means one worker per worker core
+    #[serde(default)]
+    pub cpu_layout: CpuLayout,
+    #[serde(default = "default_queue_size")]
+    pub ctl_queue_size: usize,
+}
+
+#[derive(Default, Debug, Deserialize, Clone, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CpuLayout {
+    // http acceptors and slave/cron on the first physical core, workers on the others
+    #[default]
+    Auto,
+    Manual(CoreAssignment),
+}
+
+#[derive(Default, Debug, Deserialize, Clone, Eq, PartialEq)]
+pub struct CoreAssignment {
+    pub http: Vec<usize>, // acceptor threads are spread over these round-robin
+    pub slave: usize,
+    pub cron: usize,
+    pub workers: Vec<usize>,
 }
 
 #[derive(Default, Debug, Deserialize, Copy, Clone, Eq, PartialEq)]
@@ -41,12 +252,398 @@ pub struct Worker {
     pub need_multi: bool,
 }
 
+impl Engine {
+    // resolves cpu_layout into exactly one core per worker
+    pub fn core_assignment(
+        &self,
+        topology: &CpuTopology,
+    ) -> std::result::Result<CoreAssignment, String> {
+        let cores_count = topology.pus_count();
+        if cores_count == 0 {
+            return Err("no cores available".to_string());
+        }
+        let layout = match &self.cpu_layout {
+            CpuLayout::Auto => auto_layout(topology),
+            CpuLayout::Manual(layout) => {
+                let roles = [("slave", layout.slave), ("cron", layout.cron)];
+                let http = layout.http.iter().map(|core| ("http", *core));
+                let workers = layout.workers.iter().map(|core| ("worker", *core));
+                for (role, core) in roles.into_iter().chain(http).chain(workers) {
+                    if core >= cores_count {
+                        return Err(format!(
+                            "core {} for {} doesn't exist, cores available: {}",
+                            core, role, cores_count
+                        ));
+                    }
+                }
+                if layout.workers.is_empty() {
+                    return Err("no cores assigned to workers".to_string());
+                }
+                if layout.http.is_empty() {
+                    return Err("no cores assigned to http".to_string());
+                }
+                layout.clone()
+            }
+        };
+
+        let workers_count = match self.workers_count {
+            0 => layout.workers.len(),
+            n => n,
+        };
+        Ok(CoreAssignment {
+            workers: layout
+                .workers
+                .iter()
+                .cycle()
+                .take(workers_count)
+                .copied()
+                .collect(),
+            ..layout
+        })
+    }
+}
+
+// service threads share the first physical core, workers get the others, so none of them
+// runs next to a service thread on an SMT sibling; workers take the first PU of every core,
+// package by package, before any sibling
+fn auto_layout(topology: &CpuTopology) -> CoreAssignment {
+    let mut cores = topology
+        .packages
+        .iter()
+        .flatten()
+        .filter(|pus| !pus.is_empty())
+        .collect::<Vec<_>>();
+    let first = cores.remove(0);
+    let http = first[0];
+    let service = match first.get(1) {
+        Some(sibling) => *sibling,
+        None if !cores.is_empty() => cores.remove(0)[0],
+        None => http,
+    };
+    let smt_depth = cores.iter().map(|pus| pus.len()).max().unwrap_or(0);
+    let mut workers = (0..smt_depth)
+        .flat_map(|level| cores.iter().filter_map(move |pus| pus.get(level).copied()))
+        .collect::<Vec<_>>();
+    if workers.is_empty() {
+        workers.push(service);
+    }
+    CoreAssignment {
+        http: vec![http],
+        slave: service,
+        cron: service,
+        workers,
+    }
+}
+
+// changed options split by whether they can be applied to a running server
+#[derive(Default, Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct ConfigDiff {
+    pub applied: Vec<String>,
+    pub restart_required: Vec<String>,
+}
+
+impl AdminService {
+    // admin listener reuses the search listener code, without deadlines: renders may be slow
+    pub fn as_service(&self) -> Service {
+        Service {
+            listening_port: self.listening_port,
+            bind_hosts: self.bind_hosts.clone(),
+            acceptor_threads: 1,
+            unix_socket: None,
+            task_queue_size: self.task_queue_size,
+            request_timeout_ms: 0,
+            retry_after_sec: default_retry_after_sec(),
+            admin: None,
+            templates_dir: None,
+        }
+    }
+}
+
+impl Updater {
+    // copies the fields applied without a restart, true if any of them changed
+    pub fn take_live(&mut self, new: &Updater) -> bool {
+        let live = Updater {
+            swap_interval: new.swap_interval,
+            store_switch_timeout_ms: new.store_switch_timeout_ms,
+            history: new.history.clone(),
+            snapshot: new.snapshot.clone(),
+            memory_limit_mb: new.memory_limit_mb,
+            load: new.load.clone(),
+            ..self.clone()
+        };
+        let changed = live != *self;
+        *self = live;
+        changed
+    }
+
+    // db and source make a single source named "default" when sources isn't set
+    pub fn named_sources(&self) -> std::result::Result<Vec<NamedSource>, String> {
+        if self.sources.is_empty() {
+            return Ok(vec![NamedSource {
+                name: String::from("default"),
+                db: self.db.clone(),
+                server_id: match self.server_id {
+                    0 => DEFAULT_SERVER_ID,
+                    server_id => server_id,
+                },
+                source: self.source.clone(),
+                tables: Vec::new(),
+            }]);
+        }
+        let mut sources = self.sources.clone();
+        for (num, source) in sources.iter_mut().enumerate() {
+            if source.server_id == 0 {
+                source.server_id = DEFAULT_SERVER_ID - num as u32;
+            }
+        }
+        let mut names = HashSet::new();
+        let mut tables = HashSet::new();
+        let mut server_ids = HashSet::new();
+        for source in sources.iter() {
+            if !names.insert(source.name.as_str()) {
+                return Err(format!("duplicate source name {}", source.name));
+            }
+            if source.tables.is_empty() && self.sources.len() > 1 {
+                return Err(format!("source {} needs tables", source.name));
+            }
+            for table in source.tables.iter() {
+                if !tables.insert(table.as_str()) {
+                    return Err(format!("table {} is in more than one source", table));
+                }
+            }
+            // a master drops a replica connection when another one comes with the same id
+            if source.source == Source::Mysql && !server_ids.insert(source.server_id) {
+                return Err(format!(
+                    "server_id {} of source {} is taken",
+                    source.server_id, source.name
+                ));
+            }
+        }
+        Ok(sources)
+    }
+}
+
 impl Server {
     pub fn from_file(path: &str) -> Result<Server> {
-        let mut file = std::fs::File::open(path).expect("file should open read only");
+        let mut file = std::fs::File::open(path)?;
         let mut file_content = String::new();
-        file.read_to_string(&mut file_content).unwrap();
+        file.read_to_string(&mut file_content)?;
         let conf: Server = serde_json::from_str(file_content.as_str())?;
         Ok(conf)
     }
+
+    pub fn diff(&self, new: &Server) -> ConfigDiff {
+        let mut diff = ConfigDiff::default();
+        let mut check = |name: &str, changed: bool, live: bool| match (changed, live) {
+            (true, true) => diff.applied.push(name.to_string()),
+            (true, false) => diff.restart_required.push(name.to_string()),
+            _ => {}
+        };
+        check("service", self.service != new.service, false);
+        check(
+            "engine.worker",
+            self.engine.worker != new.engine.worker,
+            true,
+        );
+        check(
+            "engine.workers_count",
+            self.engine.workers_count != new.engine.workers_count,
+            false,
+        );
+        check(
+            "engine.cpu_layout",
+            self.engine.cpu_layout != new.engine.cpu_layout,
+            false,
+        );
+        check(
+            "engine.ctl_queue_size",
+            self.engine.ctl_queue_size != new.engine.ctl_queue_size,
+            false,
+        );
+        check("updater.db", self.updater.db != new.updater.db, false);
+        check(
+            "updater.server_id",
+            self.updater.server_id != new.updater.server_id,
+            false,
+        );
+        check(
+            "updater.source",
+            self.updater.source != new.updater.source,
+            false,
+        );
+        check(
+            "updater.sources",
+            self.updater.sources != new.updater.sources,
+            false,
+        );
+        check(
+            "updater.swap_interval",
+            self.updater.swap_interval != new.updater.swap_interval,
+            true,
+        );
+        check(
+            "updater.store_switch_timeout_ms",
+            self.updater.store_switch_timeout_ms != new.updater.store_switch_timeout_ms,
+            true,
+        );
+        check(
+            "updater.memory_limit_mb",
+            self.updater.memory_limit_mb != new.updater.memory_limit_mb,
+            true,
+        );
+        check(
+            "updater.mapped_dir",
+            self.updater.mapped_dir != new.updater.mapped_dir,
+            false,
+        );
+        check(
+            "updater.snapshot",
+            self.updater.snapshot != new.updater.snapshot,
+            true,
+        );
+        check("updater.load", self.updater.load != new.updater.load, true);
+        check(
+            "updater.history",
+            self.updater.history != new.updater.history,
+            true,
+        );
+        check(
+            "admin_actions",
+            self.admin_actions != new.admin_actions,
+            true,
+        );
+        diff
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_engine_core_assignment_auto() {
+        let mut conf = Engine::default();
+        let given = conf.core_assignment(&CpuTopology::flat(8)).unwrap();
+        assert_eq!((vec![0], 1, 1), (given.http, given.slave, given.cron));
+        assert_eq!(vec![2, 3, 4, 5, 6, 7], given.workers);
+
+        let single = conf.core_assignment(&CpuTopology::flat(1)).unwrap();
+        assert_eq!(
+            (vec![0], 0, vec![0]),
+            (single.http, single.slave, single.workers)
+        );
+
+        // two packages of two cores with two threads each
+        let smt = CpuTopology {
+            packages: vec![vec![vec![0, 1], vec![2, 3]], vec![vec![4, 5], vec![6, 7]]],
+        };
+        let given = conf.core_assignment(&smt).unwrap();
+        assert_eq!((vec![0], 1, 1), (given.http, given.slave, given.cron));
+        assert_eq!(vec![2, 4, 6, 3, 5, 7], given.workers);
+        let smt = CpuTopology {
+            packages: vec![vec![vec![0, 1]]],
+        };
+        assert_eq!(vec![1], conf.core_assignment(&smt).unwrap().workers);
+
+        conf.workers_count = 3;
+        let given = conf.core_assignment(&CpuTopology::flat(4)).unwrap();
+        assert_eq!(vec![2, 3, 2], given.workers);
+    }
+
+    #[test]
+    fn test_engine_core_assignment_manual() {
+        let mut conf = Engine {
+            cpu_layout: CpuLayout::Manual(CoreAssignment {
+                http: vec![0],
+                slave: 1,
+                cron: 1,
+                workers: vec![2, 3],
+            }),
+            ..Default::default()
+        };
+        let four = CpuTopology::flat(4);
+        assert_eq!(vec![2, 3], conf.core_assignment(&four).unwrap().workers);
+        assert!(conf.core_assignment(&CpuTopology::flat(3)).is_err());
+
+        conf.cpu_layout = CpuLayout::Manual(CoreAssignment::default());
+        assert!(conf.core_assignment(&four).is_err());
+    }
+
+    #[test]
+    fn test_server_diff() {
+        let cur = Server::default();
+        let mut new = cur.clone();
+        assert_eq!(ConfigDiff::default(), cur.diff(&new));
+
+        new.updater.swap_interval = 10;
+        new.engine.worker.need_multi = true;
+        new.service.listening_port = 8089;
+        new.admin_actions.token = Some(String::from("secret"));
+        let given = cur.diff(&new);
+        assert_eq!(
+            vec!["engine.worker", "updater.swap_interval", "admin_actions"],
+            given.applied
+        );
+        assert_eq!(vec!["service"], given.restart_required);
+    }
+
+    #[test]
+    fn test_updater_take_live() {
+        let mut conf = Updater::default();
+        let new = Updater {
+            swap_interval: 5,
+            mapped_dir: Some(String::from("/dev/shm")),
+            ..Default::default()
+        };
+        assert!(conf.take_live(&new));
+        assert_eq!((5, None), (conf.swap_interval, conf.mapped_dir.as_deref()));
+        assert!(!conf.take_live(&new));
+    }
+
+    #[test]
+    fn test_updater_named_sources() {
+        let mut conf = Updater::default();
+        let given = conf.named_sources().unwrap();
+        assert_eq!(
+            vec!["default"],
+            given.iter().map(|s| s.name.as_str()).collect::<Vec<_>>()
+        );
+
+        conf.sources = serde_json::from_str(
+            r#"[{"name": "ads", "tables": ["campaign", "package"]},
+                {"name": "pads", "source": {"file": {"snapshot": "s", "events": "e"}},
+                 "tables": ["pad"]}]"#,
+        )
+        .unwrap();
+        assert_eq!(2, conf.named_sources().unwrap().len());
+
+        conf.sources
+            .push(serde_json::from_str(r#"{"name": "ads2", "tables": ["package"]}"#).unwrap());
+        conf.sources[0].tables.pop();
+        let named = conf.named_sources().unwrap();
+        assert_eq!((65535, 65533), (named[0].server_id, named[2].server_id));
+        conf.sources[2].server_id = 65535;
+        assert!(conf.named_sources().is_err());
+        conf.sources.pop();
+        conf.sources[0].tables.push(String::from("package"));
+
+        conf.sources[1].tables.push(String::from("campaign"));
+        assert!(conf.named_sources().is_err());
+        conf.sources[1].tables.clear();
+        assert!(conf.named_sources().is_err());
+    }
+
+    #[test]
+    fn test_engine_cpu_layout_from_json() {
+        let given: Engine = serde_json::from_str(r#"{"worker": {"need_multi": true}}"#).unwrap();
+        assert_eq!(CpuLayout::Auto, given.cpu_layout);
+
+        let given: Engine = serde_json::from_str(
+            r#"{"worker": {"need_multi": true}, "workers_count": 4,
+                "cpu_layout": {"manual": {"http": [0], "slave": 1, "cron": 1, "workers": [2]}}}"#,
+        )
+        .unwrap();
+        assert_eq!(4, given.workers_count);
+        assert!(matches!(given.cpu_layout, CpuLayout::Manual(ref l) if l.workers == vec![2]));
+    }
 }
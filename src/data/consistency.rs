@@ -0,0 +1,154 @@
+use crate::data::objects::IdType;
+use crate::data::raw_storage::Storage;
+use crate::data::registry;
+use crate::data::source::Positions;
+use serde::Serialize;
+use std::collections::HashSet;
+
+// ids listed per kind of difference, the counts are always exact
+pub const MAX_REPORTED_IDS: usize = 1000;
+
+pub type ObjectKey = (&'static str, IdType);
+
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize)]
+pub struct DiffIds {
+    pub count: usize,
+    pub ids: Vec<IdType>,
+}
+
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize)]
+pub struct TableDiff {
+    pub table: &'static str,
+    pub compared: usize,
+    pub skipped: usize,      // changed while compared, expected to differ
+    pub missing: DiffIds,    // in reference only
+    pub extra: DiffIds,      // in checked only
+    pub mismatched: DiffIds, // present in both with different values
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConsistencyReport {
+    pub iteration: u64,
+    pub duration_ms: u64,
+    pub consistent: bool,
+    pub stores: Vec<TableDiff>,     // published snapshot vs write_store
+    pub db: Option<Vec<TableDiff>>, // fresh select vs write_store
+    pub db_positions: Option<Positions>, // the fresh select is at or past them
+}
+
+impl DiffIds {
+    fn push(&mut self, id: IdType) {
+        self.count += 1;
+        if self.ids.len() < MAX_REPORTED_IDS {
+            self.ids.push(id);
+        }
+    }
+}
+
+impl TableDiff {
+    pub fn is_empty(&self) -> bool {
+        self.missing.count == 0 && self.extra.count == 0 && self.mismatched.count == 0
+    }
+}
+
+// compares every registered table object by object through its serialized form
+pub fn diff(reference: &Storage, checked: &Storage, skip: &HashSet<ObjectKey>) -> Vec<TableDiff> {
+    registry::object_types()
+        .iter()
+        .map(|object_type| {
+            let mut diff = TableDiff {
+                table: object_type.table,
+                ..Default::default()
+            };
+            let reference_ids = (object_type.list)(reference);
+            let checked_ids = (object_type.list)(checked);
+            let checked_set = checked_ids.iter().collect::<HashSet<_>>();
+            let reference_set = reference_ids.iter().collect::<HashSet<_>>();
+
+            for id in reference_ids.iter() {
+                if skip.contains(&(object_type.table, *id)) {
+                    diff.skipped += 1;
+                    continue;
+                }
+                diff.compared += 1;
+                if !checked_set.contains(id) {
+                    diff.missing.push(*id);
+                } else if (object_type.to_json)(reference, *id)
+                    != (object_type.to_json)(checked, *id)
+                {
+                    diff.mismatched.push(*id);
+                }
+            }
+            for id in checked_ids.iter() {
+                if reference_set.contains(id) {
+                    continue;
+                }
+                if skip.contains(&(object_type.table, *id)) {
+                    diff.skipped += 1;
+                    continue;
+                }
+                diff.compared += 1;
+                diff.extra.push(*id);
+            }
+            diff
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::objects::{Campaign, Package};
+
+    #[test]
+    fn test_diff() {
+        let mut reference = Storage::default();
+        let mut checked = Storage::default();
+        for id in 1..=4 {
+            let campaign = Campaign {
+                id,
+                name: format!("campaign {}", id),
+                ..Default::default()
+            };
+            reference.update(campaign.clone());
+            checked.update(campaign);
+        }
+        reference.update(Package {
+            id: 10,
+            ..Default::default()
+        });
+
+        checked.delete(Campaign {
+            id: 1,
+            ..Default::default()
+        });
+        checked.update(Campaign {
+            id: 2,
+            name: String::from("renamed"),
+            ..Default::default()
+        });
+        checked.update(Campaign {
+            id: 5,
+            ..Default::default()
+        });
+        checked.update(Campaign {
+            id: 6,
+            ..Default::default()
+        });
+
+        let skip = HashSet::from([("campaign", 6)]);
+        let diffs = diff(&reference, &checked, &skip);
+        let campaign = diffs.iter().find(|d| d.table == "campaign").unwrap();
+        assert_eq!(vec![1], campaign.missing.ids);
+        assert_eq!(vec![5], campaign.extra.ids);
+        assert_eq!(vec![2], campaign.mismatched.ids);
+        assert_eq!((5, 1), (campaign.compared, campaign.skipped));
+
+        let package = diffs.iter().find(|d| d.table == "package").unwrap();
+        assert_eq!(vec![10], package.missing.ids);
+        assert!(diffs
+            .iter()
+            .filter(|d| d.table != "campaign" && d.table != "package")
+            .all(|d| d.is_empty()));
+    }
+}
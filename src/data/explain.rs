@@ -0,0 +1,242 @@
+use crate::data::objects::{Campaign, IdType, PadRelation, TargetingPad};
+use crate::data::raw_storage::Storage;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+pub const ACTIVE_STATUS: &str = "active";
+const CAMPAIGN_OBJECT_TYPE: &str = "campaign";
+
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Filter {
+    Status,
+    FlightDates,
+    PositiveTargeting,
+    NegativeTargeting,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FilterVerdict {
+    pub filter: Filter,
+    pub passed: bool,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CampaignExplain {
+    pub campaign_id: IdType,
+    pub name: String,
+    pub passed: bool,
+    pub verdicts: Vec<FilterVerdict>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Explain {
+    pub pad_id: IdType,
+    pub pad_chain: Vec<IdType>, // requested pad followed by its ancestors
+    pub now_ts: i64,
+    pub campaigns: Vec<CampaignExplain>,
+}
+
+// campaign_id == 0 means all campaigns are candidates
+pub fn explain(storage: &Storage, pad_id: IdType, campaign_id: IdType, now_ts: i64) -> Explain {
+    let pad_chain = pad_chain(storage, pad_id);
+    let mut campaigns = storage
+        .iter::<Campaign>()
+        .filter(|c| campaign_id == 0 || c.id == campaign_id)
+        .map(|c| explain_campaign(storage, &c, &pad_chain, now_ts))
+        .collect::<Vec<_>>();
+    campaigns.sort_by_key(|c| c.campaign_id);
+
+    Explain {
+        pad_id,
+        pad_chain,
+        now_ts,
+        campaigns,
+    }
+}
+
+pub fn explain_campaign(
+    storage: &Storage,
+    campaign: &Campaign,
+    pad_chain: &[IdType],
+    now_ts: i64,
+) -> CampaignExplain {
+    let rules = storage
+        .iter::<TargetingPad>()
+        .filter(|t| t.object_type == CAMPAIGN_OBJECT_TYPE && t.object_id == campaign.id)
+        .collect::<Vec<_>>();
+    let rules = rules.iter().map(|t| t.as_ref()).collect::<Vec<_>>();
+
+    let verdicts = vec![
+        check_status(campaign),
+        check_flight_dates(campaign, now_ts),
+        check_positive_targeting(&rules, pad_chain),
+        check_negative_targeting(&rules, pad_chain),
+    ];
+
+    CampaignExplain {
+        campaign_id: campaign.id,
+        name: campaign.name.clone(),
+        passed: verdicts.iter().all(|v| v.passed),
+        verdicts,
+    }
+}
+
+// walks PadRelation up from the given pad; guards against cycles in the data
+pub fn pad_chain(storage: &Storage, pad_id: IdType) -> Vec<IdType> {
+    let mut chain = vec![pad_id];
+    let mut visited = HashSet::from([pad_id]);
+    let mut cur = pad_id;
+    while let Some(rel) = storage.iter::<PadRelation>().find(|r| r.pad_id == cur) {
+        if !visited.insert(rel.parent_pad_id) {
+            log::warn!("pad_chain: cycle detected for pad_id={}", pad_id);
+            break;
+        }
+        chain.push(rel.parent_pad_id);
+        cur = rel.parent_pad_id;
+    }
+    chain
+}
+
+fn check_status(campaign: &Campaign) -> FilterVerdict {
+    FilterVerdict {
+        filter: Filter::Status,
+        passed: campaign.status == ACTIVE_STATUS,
+        reason: format!("status='{}'", campaign.status),
+    }
+}
+
+fn check_flight_dates(campaign: &Campaign, now_ts: i64) -> FilterVerdict {
+    let (start, end) = (campaign.flight_start_ts, campaign.flight_end_ts);
+    let (passed, reason) = if start != 0 && now_ts < start {
+        (false, format!("not started yet: start_ts={}", start))
+    } else if end != 0 && now_ts > end {
+        (false, format!("already finished: end_ts={}", end))
+    } else {
+        (
+            true,
+            format!("in flight: start_ts={}, end_ts={}", start, end),
+        )
+    };
+    FilterVerdict {
+        filter: Filter::FlightDates,
+        passed,
+        reason,
+    }
+}
+
+fn check_positive_targeting(rules: &[&TargetingPad], pad_chain: &[IdType]) -> FilterVerdict {
+    let positive = rules.iter().filter(|r| r.positive).collect::<Vec<_>>();
+    let (passed, reason) = if positive.is_empty() {
+        (true, "no positive rules".to_string())
+    } else {
+        match positive.iter().find(|r| pad_chain.contains(&r.pad_id)) {
+            Some(rule) => (
+                true,
+                format!("matched rule id={} (pad_id={})", rule.id, rule.pad_id),
+            ),
+            None => (
+                false,
+                format!("none of {} positive rules match pad chain", positive.len()),
+            ),
+        }
+    };
+    FilterVerdict {
+        filter: Filter::PositiveTargeting,
+        passed,
+        reason,
+    }
+}
+
+fn check_negative_targeting(rules: &[&TargetingPad], pad_chain: &[IdType]) -> FilterVerdict {
+    let (passed, reason) = match rules
+        .iter()
+        .find(|r| !r.positive && pad_chain.contains(&r.pad_id))
+    {
+        Some(rule) => (
+            false,
+            format!("rejected by rule id={} (pad_id={})", rule.id, rule.pad_id),
+        ),
+        None => (true, "no negative rules match pad chain".to_string()),
+    };
+    FilterVerdict {
+        filter: Filter::NegativeTargeting,
+        passed,
+        reason,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn campaign(id: IdType, status: &str) -> Campaign {
+        Campaign {
+            id,
+            status: status.into(),
+            ..Default::default()
+        }
+    }
+
+    fn targeting(id: IdType, campaign_id: IdType, pad_id: IdType, positive: bool) -> TargetingPad {
+        TargetingPad {
+            id,
+            object_id: campaign_id,
+            object_type: CAMPAIGN_OBJECT_TYPE.into(),
+            pad_id,
+            positive,
+        }
+    }
+
+    #[test]
+    fn test_explain_targeting_with_pad_hierarchy() {
+        let mut storage = Storage::default();
+        storage.update(campaign(1, ACTIVE_STATUS));
+        storage.update(campaign(2, ACTIVE_STATUS));
+        storage.update(campaign(3, "paused"));
+        storage.update(PadRelation {
+            id: 1,
+            pad_id: 10,
+            parent_pad_id: 20,
+        });
+        storage.update(targeting(1, 1, 20, true));
+        storage.update(targeting(2, 2, 20, false));
+
+        let given = explain(&storage, 10, 0, 0);
+        assert_eq!(vec![10, 20], given.pad_chain);
+
+        let passed = given
+            .campaigns
+            .iter()
+            .map(|c| (c.campaign_id, c.passed))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![(1, true), (2, false), (3, false)], passed);
+
+        let rejected_by = |id: IdType| {
+            given.campaigns[id as usize - 1]
+                .verdicts
+                .iter()
+                .filter(|v| !v.passed)
+                .map(|v| v.filter.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec![Filter::NegativeTargeting], rejected_by(2));
+        assert_eq!(vec![Filter::Status], rejected_by(3));
+    }
+
+    #[test]
+    fn test_explain_flight_dates() {
+        let mut storage = Storage::default();
+        storage.update(Campaign {
+            flight_start_ts: 100,
+            flight_end_ts: 200,
+            ..campaign(1, ACTIVE_STATUS)
+        });
+
+        for (now_ts, expected) in [(50, false), (150, true), (250, false)] {
+            let given = explain(&storage, 0, 1, now_ts);
+            assert_eq!(expected, given.campaigns[0].passed);
+        }
+    }
+}
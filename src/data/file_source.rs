@@ -0,0 +1,317 @@
+use crate::config;
+use crate::data::history::EventOrigin;
+use crate::data::registry;
+use crate::data::source::{self, ChangeSource, ChangeStream, SourceEvent, SourceObject};
+use crate::data::updater::EventType;
+use crate::helpers;
+
+use logging_timer::stime;
+use serde::Deserialize;
+use serde_json::Value;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+// events returned by one next_events call
+const BATCH_SIZE: usize = 1000;
+// longest sleep inside next_events, keeps the slave responsive to stop
+const MAX_WAIT: Duration = Duration::from_millis(100);
+
+// jsonl files instead of a database, to replay incidents and test without mysql:
+//   snapshot: {"table": "campaign", "object": {...}}
+//   events:   {"event": "insert|update|delete", "table": "campaign", "object": {...},
+//              "old": {...}, "ts": 1700000000}, old and ts are optional
+// the position is the number of the last applied events line, the snapshot is at 0
+pub struct FileSource {
+    conf: config::FileSource,
+    tables: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SnapshotLine {
+    table: String,
+    object: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct EventLine {
+    event: String,
+    table: String,
+    object: Value,
+    #[serde(default)]
+    old: Option<Value>,
+    #[serde(default)]
+    ts: Option<u64>,
+}
+
+struct FileStream {
+    path: String,
+    tables: Vec<String>,
+    reader: BufReader<File>,
+    line_no: u64,
+    skip_to: u64,
+    partial: String, // last line while the writer hasn't finished it
+    paced: bool,
+    pace_start: Option<(Instant, u64)>, // when the first timed event was replayed, its ts
+    pending: Option<(u64, EventLine)>,  // read but not due yet
+}
+
+impl FileSource {
+    pub fn new(conf: &config::FileSource, tables: &[String]) -> Self {
+        FileSource {
+            conf: conf.clone(),
+            tables: tables.to_vec(),
+        }
+    }
+}
+
+impl ChangeSource for FileSource {
+    fn name(&self) -> String {
+        format!("file://{}", self.conf.events)
+    }
+
+    fn position(&self) -> Result<Option<String>, Box<dyn Error>> {
+        Ok(Some(String::from("0")))
+    }
+
+    fn tables(&self) -> Vec<&'static str> {
+        source::source_tables(&self.tables)
+    }
+
+    // the snapshot has every table mixed, so each table reads it through
+    #[stime("info")]
+    fn load_table(
+        &self,
+        table: &str,
+        chunk_size: usize,
+        f: &mut dyn FnMut(Vec<SourceObject>),
+    ) -> Result<(), Box<dyn Error>> {
+        let reader = BufReader::new(File::open(&self.conf.snapshot)?);
+        let mut chunk = Vec::with_capacity(chunk_size);
+        for (pos, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let parsed = serde_json::from_str::<SnapshotLine>(&line)
+                .map_err(|e| e.to_string())
+                .and_then(|l| match l.table == table {
+                    true => parse_object(&l.table, l.object).map(Some),
+                    false => Ok(None),
+                });
+            match parsed {
+                Ok(Some(object)) => chunk.push(object),
+                Ok(None) => {}
+                Err(e) => return Err(format!("{}:{}: {}", self.conf.snapshot, pos + 1, e).into()),
+            }
+            if chunk.len() >= chunk_size {
+                f(std::mem::replace(
+                    &mut chunk,
+                    Vec::with_capacity(chunk_size),
+                ));
+            }
+        }
+        if !chunk.is_empty() {
+            f(chunk);
+        }
+        Ok(())
+    }
+
+    fn stream(&self, position: Option<String>) -> Result<Box<dyn ChangeStream>, Box<dyn Error>> {
+        let skip_to = match position {
+            Some(position) => position
+                .parse()
+                .map_err(|e| format!("bad file position '{}': {}", position, e))?,
+            None => 0,
+        };
+        Ok(Box::new(FileStream {
+            path: self.conf.events.clone(),
+            tables: self.tables.clone(),
+            reader: BufReader::new(File::open(&self.conf.events)?),
+            line_no: 0,
+            skip_to,
+            partial: String::new(),
+            paced: self.conf.paced,
+            pace_start: None,
+            pending: None,
+        }))
+    }
+}
+
+impl ChangeStream for FileStream {
+    fn next_events(&mut self) -> Result<Vec<SourceEvent>, Box<dyn Error>> {
+        let mut events = Vec::new();
+        while events.len() < BATCH_SIZE {
+            let (line_no, line) = match self.pending.take() {
+                Some(pending) => pending,
+                None => match self.read_event()? {
+                    Some(read) => read,
+                    None => {
+                        if events.is_empty() {
+                            sleep(MAX_WAIT);
+                        }
+                        break;
+                    }
+                },
+            };
+            if let Some(wait) = self.wait_for(&line) {
+                self.pending = Some((line_no, line));
+                if events.is_empty() {
+                    sleep(wait.min(MAX_WAIT));
+                }
+                break;
+            }
+
+            let position = line_no.to_string();
+            if source::has_table(&self.tables, &line.table) {
+                match to_change(line, &position) {
+                    Ok(change) => events.push(change),
+                    Err(e) => log::error!("{}:{}: skip event: {}", self.path, line_no, e),
+                }
+            }
+            events.push(SourceEvent::Commit { position });
+        }
+        Ok(events)
+    }
+}
+
+impl FileStream {
+    // next complete events line past skip_to, None at the end of the file for now
+    fn read_event(&mut self) -> Result<Option<(u64, EventLine)>, Box<dyn Error>> {
+        loop {
+            if self.reader.read_line(&mut self.partial)? == 0 || !self.partial.ends_with('\n') {
+                return Ok(None);
+            }
+            let line = std::mem::take(&mut self.partial);
+            self.line_no += 1;
+            if self.line_no <= self.skip_to || line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<EventLine>(&line) {
+                Ok(event) => return Ok(Some((self.line_no, event))),
+                Err(e) => log::error!("{}:{}: skip line: {}", self.path, self.line_no, e),
+            }
+        }
+    }
+
+    // time left until the event is due, None when it can be applied now
+    fn wait_for(&mut self, line: &EventLine) -> Option<Duration> {
+        let ts = line.ts.filter(|_| self.paced)?;
+        let (start, start_ts) = *self.pace_start.get_or_insert((Instant::now(), ts));
+        let due = start + Duration::from_secs(ts.saturating_sub(start_ts));
+        due.checked_duration_since(Instant::now())
+            .filter(|wait| !wait.is_zero())
+    }
+}
+
+fn to_change(line: EventLine, position: &str) -> Result<SourceEvent, String> {
+    let ev_type = match line.event.as_str() {
+        "insert" => EventType::Insert,
+        "update" => EventType::Update,
+        "delete" => EventType::Delete,
+        other => return Err(format!("unknown event '{}'", other)),
+    };
+    let old = match line.old {
+        Some(old) => Some(parse_object(&line.table, old)?),
+        None => None,
+    };
+    Ok(SourceEvent::Change {
+        ev_type,
+        object: parse_object(&line.table, line.object)?,
+        old,
+        origin: EventOrigin {
+            gtid: Some(position.to_string()),
+            binlog_ts: line.ts.unwrap_or_else(helpers::time::cur_ts),
+        },
+    })
+}
+
+fn parse_object(table: &str, object: Value) -> Result<SourceObject, String> {
+    let object_type = registry::find(table).ok_or(format!("unknown table '{}'", table))?;
+    (object_type.from_json)(object).map_err(|e| format!("bad {} object: {}", table, e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    fn write_lines(name: &str, lines: &[&str]) -> String {
+        let path = std::env::temp_dir().join(format!("indexerd-{}-{}", name, std::process::id()));
+        let mut file = File::create(&path).unwrap();
+        for line in lines {
+            writeln!(file, "{}", line).unwrap();
+        }
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_file_source() {
+        let conf = config::FileSource {
+            snapshot: write_lines(
+                "snapshot",
+                &[
+                    r#"{"table": "package", "object": {"id": 10, "name": "p"}}"#,
+                    "",
+                    r#"{"table": "pad", "object": {"id": 3, "name": "pad"}}"#,
+                ],
+            ),
+            events: write_lines(
+                "events",
+                &[
+                    r#"{"event": "insert", "table": "pad", "object": {"id": 4, "name": "new"}, "ts": 100}"#,
+                    r#"{"event": "rename", "table": "pad", "object": {"id": 4, "name": "x"}}"#,
+                    r#"{"event": "delete", "table": "pad", "object": {"id": 3, "name": "pad"}, "ts": 101}"#,
+                ],
+            ),
+            paced: false,
+        };
+        let source = FileSource::new(&conf, &[]);
+
+        let mut loaded = Vec::new();
+        for table in source.tables() {
+            source
+                .load_table(table, 10, &mut |chunk| loaded.extend(chunk))
+                .unwrap();
+        }
+        assert_eq!(2, loaded.len());
+        assert!(matches!(loaded[1], SourceObject::Pad(ref pad) if pad.id == 3));
+
+        let mut stream = source.stream(source.position().unwrap()).unwrap();
+        let events = stream.next_events().unwrap();
+        let commits = events
+            .iter()
+            .filter_map(|event| match event {
+                SourceEvent::Commit { position } => Some(position.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        // the unknown event is skipped but still committed
+        assert_eq!(vec!["1", "2", "3"], commits);
+        assert!(matches!(
+            events[0],
+            SourceEvent::Change {
+                ev_type: EventType::Insert,
+                origin: EventOrigin { binlog_ts: 100, .. },
+                ..
+            }
+        ));
+        assert!(stream.next_events().unwrap().is_empty());
+
+        let mut resumed = source.stream(Some(String::from("2"))).unwrap();
+        let events = resumed.next_events().unwrap();
+        assert_eq!(2, events.len());
+        assert!(matches!(
+            events[0],
+            SourceEvent::Change {
+                ev_type: EventType::Delete,
+                ..
+            }
+        ));
+
+        let _ = std::fs::remove_file(&conf.snapshot);
+        let _ = std::fs::remove_file(&conf.events);
+    }
+}
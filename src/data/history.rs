@@ -0,0 +1,210 @@
+use crate::config;
+use crate::data::consistency::ObjectKey;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Mutex;
+
+// publish times kept to resolve visible_ts, far more than any ring spans in practice
+const PUBLISHED_DEPTH: usize = 1000;
+
+// where a change came from in the binlog
+#[derive(Debug, Clone, Default)]
+pub struct EventOrigin {
+    pub gtid: Option<String>,
+    pub binlog_ts: u64, // master commit time
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct Change {
+    pub event: &'static str,
+    pub old: Option<Value>,
+    pub new: Option<Value>, // None for deletes
+    pub gtid: Option<String>,
+    pub binlog_ts: u64,
+    pub applied_ts: u64,         // when write_store got it
+    pub visible_iteration: u64,  // first published snapshot containing the change
+    pub visible_ts: Option<u64>, // None until that snapshot is published
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+// bounded rings of recent slave changes per object, shared by the updater and admin workers
+#[derive(Default)]
+pub struct ChangeHistory {
+    inner: Mutex<Rings>,
+}
+
+#[derive(Default)]
+struct Rings {
+    conf: config::History,
+    seq: u64,
+    objects: HashMap<ObjectKey, (u64, VecDeque<Change>)>, // last change seq, oldest change first
+    order: BTreeMap<u64, ObjectKey>,                      // objects by last change seq
+    published: VecDeque<(u64, u64)>,                      // iteration, publish ts
+}
+
+impl ChangeHistory {
+    pub fn configure(&self, conf: &config::History) {
+        let mut rings = self.inner.lock().unwrap();
+        rings.conf = conf.clone();
+        let depth = conf.depth;
+        for (_, changes) in rings.objects.values_mut() {
+            while changes.len() > depth {
+                changes.pop_front();
+            }
+        }
+        rings.evict();
+    }
+
+    // lets callers skip serializing objects when nothing is kept
+    pub fn is_enabled(&self) -> bool {
+        let rings = self.inner.lock().unwrap();
+        rings.conf.depth > 0 && rings.conf.max_objects > 0
+    }
+
+    pub fn record(&self, key: ObjectKey, change: Change) {
+        let mut rings = self.inner.lock().unwrap();
+        let depth = rings.conf.depth;
+        if depth == 0 {
+            return;
+        }
+        rings.seq += 1;
+        let seq = rings.seq;
+        let (last_seq, changes) = rings.objects.entry(key).or_default();
+        let prev_seq = std::mem::replace(last_seq, seq);
+        changes.push_back(change);
+        if changes.len() > depth {
+            changes.pop_front();
+        }
+        rings.order.remove(&prev_seq);
+        rings.order.insert(seq, key);
+        rings.evict();
+    }
+
+    pub fn mark_published(&self, iteration: u64, ts: u64) {
+        let mut rings = self.inner.lock().unwrap();
+        rings.published.push_back((iteration, ts));
+        if rings.published.len() > PUBLISHED_DEPTH {
+            rings.published.pop_front();
+        }
+    }
+
+    // newest first
+    pub fn get(&self, key: ObjectKey) -> Vec<Change> {
+        let rings = self.inner.lock().unwrap();
+        let changes = match rings.objects.get(&key) {
+            Some((_, changes)) => changes,
+            None => return Vec::new(),
+        };
+        changes
+            .iter()
+            .rev()
+            .map(|change| Change {
+                visible_ts: rings
+                    .published
+                    .iter()
+                    .find(|(iteration, _)| *iteration >= change.visible_iteration)
+                    .map(|(_, ts)| *ts),
+                ..change.clone()
+            })
+            .collect()
+    }
+}
+
+impl Rings {
+    // forgets least recently changed objects over the limit
+    fn evict(&mut self) {
+        while self.order.len() > self.conf.max_objects {
+            if let Some((_, key)) = self.order.pop_first() {
+                self.objects.remove(&key);
+            }
+        }
+    }
+}
+
+impl Change {
+    // fields which differ between old and new, in old's order
+    pub fn field_changes(&self) -> Vec<FieldChange> {
+        let empty = serde_json::Map::new();
+        let old = self
+            .old
+            .as_ref()
+            .and_then(|v| v.as_object())
+            .unwrap_or(&empty);
+        let new = self
+            .new
+            .as_ref()
+            .and_then(|v| v.as_object())
+            .unwrap_or(&empty);
+        let added = new.keys().filter(|field| !old.contains_key(*field));
+        old.keys()
+            .chain(added)
+            .filter(|field| old.get(*field) != new.get(*field))
+            .map(|field| FieldChange {
+                field: field.clone(),
+                old: old.get(field).cloned(),
+                new: new.get(field).cloned(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn change(new: Value, visible_iteration: u64) -> Change {
+        Change {
+            event: "update",
+            old: Some(json!({"id": 1, "package_id": 2, "name": "a"})),
+            new: Some(new),
+            gtid: None,
+            binlog_ts: 0,
+            applied_ts: 0,
+            visible_iteration,
+            visible_ts: None,
+        }
+    }
+
+    #[test]
+    fn test_history() {
+        let history = ChangeHistory::default();
+        history.configure(&config::History {
+            depth: 2,
+            max_objects: 2,
+        });
+        for package_id in 3..=5 {
+            history.record(
+                ("campaign", 1),
+                change(json!({"id": 1, "package_id": package_id, "name": "a"}), 2),
+            );
+        }
+        history.mark_published(1, 100);
+        history.mark_published(3, 300);
+
+        let changes = history.get(("campaign", 1));
+        assert_eq!(2, changes.len());
+        assert_eq!(Some(300), changes[0].visible_ts);
+        assert_eq!(
+            vec![FieldChange {
+                field: String::from("package_id"),
+                old: Some(json!(2)),
+                new: Some(json!(5)),
+            }],
+            changes[0].field_changes()
+        );
+
+        // campaign 1 changed least recently
+        history.record(("campaign", 2), change(json!({}), 4));
+        history.record(("package", 1), change(json!({}), 4));
+        assert!(history.get(("campaign", 1)).is_empty());
+        assert_eq!(None, history.get(("package", 1))[0].visible_ts);
+    }
+}
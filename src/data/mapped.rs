@@ -0,0 +1,305 @@
+use crate::data::objects::{Campaign, IdType, Package, Pad, PadRelation, TargetingPad};
+use crate::data::objects_traits::{DbObject, StorableRaw};
+use crate::data::raw_storage::Storage;
+use crate::data::registry;
+
+use logging_timer::stime;
+use memmap2::Mmap;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Seek, Write};
+use std::path::{Path, PathBuf};
+
+pub const MAPPED_VERSION: u32 = 1;
+const MAGIC: &[u8; 4] = b"IXDM";
+
+// immutable tables in a memory-mapped file; layout, all integers LE:
+//   MAGIC, version u32,
+//   per table: ids i32 x count (sorted), bincode objects, offsets u64 x (count + 1),
+//   directory: tables u32, per table: name_len u32, name, count u64, ids, data, offsets u64,
+//   directory offset u64
+pub struct MappedTables {
+    path: PathBuf,
+    mmap: Mmap,
+    tables: HashMap<&'static str, TableIndex>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TableIndex {
+    count: usize,
+    ids: usize, // file offsets of the sections
+    data: usize,
+    offsets: usize,
+}
+
+impl MappedTables {
+    // writes storage to path and maps it; the file is removed once the tables are dropped
+    #[stime("info")]
+    pub fn build(path: &Path, storage: &Storage) -> Result<MappedTables, Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&MAPPED_VERSION.to_le_bytes())?;
+
+        let mut directory = Vec::new();
+        write_table::<Campaign>(&mut writer, storage, &mut directory)?;
+        write_table::<Package>(&mut writer, storage, &mut directory)?;
+        write_table::<Pad>(&mut writer, storage, &mut directory)?;
+        write_table::<PadRelation>(&mut writer, storage, &mut directory)?;
+        write_table::<TargetingPad>(&mut writer, storage, &mut directory)?;
+
+        let directory_offset = writer.stream_position()?;
+        writer.write_all(&(directory.len() as u32).to_le_bytes())?;
+        for (table, index) in directory.iter() {
+            writer.write_all(&(table.len() as u32).to_le_bytes())?;
+            writer.write_all(table.as_bytes())?;
+            for value in [index.count, index.ids, index.data, index.offsets] {
+                writer.write_all(&(value as u64).to_le_bytes())?;
+            }
+        }
+        writer.write_all(&directory_offset.to_le_bytes())?;
+        // no fsync: the file is only read back by this process and never outlives it
+        writer.into_inner().map_err(|e| e.into_error())?;
+
+        Self::open(path)
+    }
+
+    fn open(path: &Path) -> Result<MappedTables, Box<dyn Error>> {
+        let file = File::open(path)?;
+        // the file is written once by build and never modified while mapped
+        let mmap = unsafe { Mmap::map(&file)? };
+        let mut mapped = MappedTables {
+            path: path.to_path_buf(),
+            mmap,
+            tables: HashMap::new(),
+        };
+        if mapped.mmap.len() < 16 || &mapped.mmap[0..4] != MAGIC {
+            return Err(format!("{} is not a mapped index", path.display()).into());
+        }
+        let version = mapped.u32_at(4);
+        if version != MAPPED_VERSION {
+            return Err(format!("mapped index version {} isn't supported", version).into());
+        }
+
+        let mut pos = mapped.u64_at(mapped.mmap.len() - 8);
+        let tables = mapped.u32_at(pos);
+        pos += 4;
+        for _ in 0..tables {
+            let name_len = mapped.u32_at(pos) as usize;
+            let name = std::str::from_utf8(&mapped.mmap[pos + 4..pos + 4 + name_len])?.to_string();
+            pos += 4 + name_len;
+            let index = TableIndex {
+                count: mapped.u64_at(pos),
+                ids: mapped.u64_at(pos + 8),
+                data: mapped.u64_at(pos + 16),
+                offsets: mapped.u64_at(pos + 24),
+            };
+            pos += 32;
+            match registry::find(&name) {
+                Some(object_type) => {
+                    mapped.tables.insert(object_type.table, index);
+                }
+                None => log::warn!("unknown table {} in {}", name, path.display()),
+            }
+        }
+        Ok(mapped)
+    }
+
+    pub fn tables(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.tables.keys().copied()
+    }
+
+    pub fn count(&self, table: &str) -> usize {
+        self.tables.get(table).map_or(0, |index| index.count)
+    }
+
+    // mapped bytes, resident or not
+    pub fn size(&self) -> usize {
+        self.mmap.len()
+    }
+
+    // ids, objects and offsets of one table
+    pub fn table_size(&self, table: &str) -> usize {
+        self.tables
+            .get(table)
+            .map_or(0, |index| index.offsets + (index.count + 1) * 8 - index.ids)
+    }
+
+    pub fn contains(&self, table: &str, id: IdType) -> bool {
+        self.tables
+            .get(table)
+            .is_some_and(|index| self.position(index, id).is_some())
+    }
+
+    // sorted
+    pub fn ids<'a>(&'a self, table: &str) -> impl Iterator<Item = IdType> + 'a {
+        let index = self.tables.get(table).copied();
+        index
+            .into_iter()
+            .flat_map(move |index| (0..index.count).map(move |i| self.id_at(&index, i)))
+    }
+
+    pub fn get<T: DbObject + DeserializeOwned>(&self, id: IdType) -> Option<T> {
+        let index = self.tables.get(T::table())?;
+        let pos = self.position(index, id)?;
+        self.object_at(index, pos)
+    }
+
+    pub fn iter<'a, T: DbObject + DeserializeOwned + 'a>(&'a self) -> impl Iterator<Item = T> + 'a {
+        let index = self.tables.get(T::table()).copied();
+        index
+            .into_iter()
+            .flat_map(move |index| (0..index.count).filter_map(move |i| self.object_at(&index, i)))
+    }
+
+    fn position(&self, index: &TableIndex, id: IdType) -> Option<usize> {
+        let (mut lo, mut hi) = (0, index.count);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match self.id_at(index, mid).cmp(&id) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => return Some(mid),
+            }
+        }
+        None
+    }
+
+    fn id_at(&self, index: &TableIndex, i: usize) -> IdType {
+        let pos = index.ids + i * 4;
+        IdType::from_le_bytes(self.mmap[pos..pos + 4].try_into().unwrap())
+    }
+
+    fn object_at<T: DeserializeOwned>(&self, index: &TableIndex, i: usize) -> Option<T> {
+        let start = index.data + self.u64_at(index.offsets + i * 8);
+        let end = index.data + self.u64_at(index.offsets + (i + 1) * 8);
+        match bincode::deserialize(&self.mmap[start..end]) {
+            Ok(object) => Some(object),
+            Err(e) => {
+                log::error!("broken object #{} in {}: {}", i, self.path.display(), e);
+                None
+            }
+        }
+    }
+
+    fn u32_at(&self, pos: usize) -> u32 {
+        u32::from_le_bytes(self.mmap[pos..pos + 4].try_into().unwrap())
+    }
+
+    fn u64_at(&self, pos: usize) -> usize {
+        u64::from_le_bytes(self.mmap[pos..pos + 8].try_into().unwrap()) as usize
+    }
+}
+
+impl Drop for MappedTables {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            log::warn!("fail to remove {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+fn write_table<T>(
+    writer: &mut BufWriter<File>,
+    storage: &Storage,
+    directory: &mut Vec<(&'static str, TableIndex)>,
+) -> Result<(), Box<dyn Error>>
+where
+    T: DbObject + StorableRaw + Serialize + DeserializeOwned + Clone + 'static,
+{
+    let mut ids = storage.list::<T>();
+    ids.sort();
+
+    let ids_pos = writer.stream_position()? as usize;
+    for id in ids.iter() {
+        writer.write_all(&id.to_le_bytes())?;
+    }
+    let data_pos = writer.stream_position()? as usize;
+    let mut offsets = Vec::with_capacity(ids.len() + 1);
+    let mut offset = 0u64;
+    for id in ids.iter() {
+        let object = storage
+            .try_get::<T>(*id)
+            .ok_or_else(|| format!("{} {} disappeared while building", T::table(), id))?;
+        let bytes = bincode::serialize(object.as_ref())?;
+        writer.write_all(&bytes)?;
+        offsets.push(offset);
+        offset += bytes.len() as u64;
+    }
+    offsets.push(offset);
+    let offsets_pos = writer.stream_position()? as usize;
+    for offset in offsets {
+        writer.write_all(&offset.to_le_bytes())?;
+    }
+
+    directory.push((
+        T::table(),
+        TableIndex {
+            count: ids.len(),
+            ids: ids_pos,
+            data: data_pos,
+            offsets: offsets_pos,
+        },
+    ));
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_mapped_storage() {
+        let mut storage = Storage::default();
+        for id in [5, 1, 3] {
+            storage.update(Campaign {
+                id,
+                name: format!("campaign {}", id),
+                ..Default::default()
+            });
+        }
+        storage.update(Pad {
+            id: 3,
+            name: String::from("pad"),
+        });
+
+        let path = std::env::temp_dir().join(format!("indexerd-mapped-{}", std::process::id()));
+        let mapped = MappedTables::build(&path, &storage).unwrap();
+        assert_eq!(vec![1, 3, 5], mapped.ids("campaign").collect::<Vec<_>>());
+        assert_eq!("campaign 3", mapped.get::<Campaign>(3).unwrap().name);
+        assert!(mapped.get::<Campaign>(2).is_none());
+        assert!(mapped.contains("pad", 3) && !mapped.contains("pad", 5));
+        assert_eq!(0, mapped.count("package"));
+
+        // heap delta over the mapped base
+        let mut layered = Storage::with_base(Arc::new(mapped));
+        layered.update(Campaign {
+            id: 3,
+            name: String::from("renamed"),
+            ..Default::default()
+        });
+        layered.update(Campaign {
+            id: 7,
+            ..Default::default()
+        });
+        layered.delete(Campaign {
+            id: 5,
+            ..Default::default()
+        });
+        let mut ids = layered.list::<Campaign>();
+        ids.sort();
+        assert_eq!(vec![1, 3, 7], ids);
+        assert_eq!("renamed", layered.try_get::<Campaign>(3).unwrap().name);
+        assert_eq!("campaign 1", layered.try_get::<Campaign>(1).unwrap().name);
+        assert!(layered.try_get::<Campaign>(5).is_none());
+        assert_eq!(3, layered.iter::<Campaign>().count());
+        let counts = layered.counts();
+        assert!(counts.contains(&("campaign", 3)) && counts.contains(&("pad", 1)));
+
+        drop(layered);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,57 @@
+use serde::{Deserialize, Serialize};
+
+// estimated memory of one table in a store
+#[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize, Serialize)]
+pub struct TableMemory {
+    pub table: String,
+    pub objects: usize,
+    pub heap_bytes: usize,   // objects, their strings and hash table slots
+    pub mapped_bytes: usize, // file pages, evictable page cache rather than heap
+}
+
+#[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize, Serialize)]
+pub struct StoreMemory {
+    pub tables: Vec<TableMemory>,
+    pub index_bytes: usize, // search structures built on top of the tables
+    pub heap_bytes: usize,  // tables and index
+    pub mapped_bytes: usize,
+}
+
+impl StoreMemory {
+    pub fn new(tables: Vec<TableMemory>, index_bytes: usize) -> Self {
+        StoreMemory {
+            heap_bytes: index_bytes + tables.iter().map(|t| t.heap_bytes).sum::<usize>(),
+            mapped_bytes: tables.iter().map(|t| t.mapped_bytes).sum(),
+            tables,
+            index_bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::objects::Campaign;
+    use crate::data::raw_storage::Storage;
+
+    #[test]
+    fn test_table_memory() {
+        let mut storage = Storage::default();
+        let before = storage.memory::<Campaign>();
+        assert_eq!((0, 0), (before.objects, before.heap_bytes));
+
+        storage.update(Campaign {
+            id: 1,
+            name: "x".repeat(1000),
+            ..Default::default()
+        });
+        let table = storage.memory::<Campaign>();
+        assert_eq!(("campaign", 1), (table.table.as_str(), table.objects));
+        assert!(table.heap_bytes > 1000 + size_of::<Campaign>());
+
+        let store = StoreMemory::new(vec![table.clone(), table], 10);
+        assert_eq!(10, store.index_bytes);
+        assert!(store.heap_bytes > 2000 + 10);
+        assert_eq!(0, store.mapped_bytes);
+    }
+}
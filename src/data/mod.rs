@@ -1,9 +1,21 @@
 pub mod aci;
+pub mod consistency;
+pub mod explain;
+pub mod file_source;
+pub mod history;
+pub mod mapped;
+pub mod memory;
 mod mysql_cdc_converter;
 pub mod objects;
 pub mod objects_traits;
+pub mod pg_source;
+pub mod query;
 mod raw_storage;
+pub mod registry;
 pub mod select;
 pub mod slave;
+pub mod snapshot;
+pub mod source;
 pub mod store;
+mod text_converter;
 pub mod updater;
@@ -1,51 +1,53 @@
 use crate::data::mysql_cdc_converter::convert;
-use crate::data::objects_traits::{MysqlObject, StorableRaw};
+use crate::data::objects_traits::{DbObject, FkTarget, ForeignKey, HeapSize, StorableRaw};
 use crate::data::slave::FieldMapping;
+use crate::data::text_converter::convert_text;
 use mysql::prelude::FromRow;
+use serde::{Deserialize, Serialize};
 
 use std::collections::HashMap;
 
 pub type IdType = i32;
 
-#[derive(Debug, Default, Clone, FromRow, MysqlObject, StorableRaw)]
+#[derive(Debug, Default, Clone, FromRow, DbObject, StorableRaw, Serialize, Deserialize)]
 pub struct Campaign {
     pub id: IdType,
     pub name: String,
+    #[fk(table = "package")]
     pub package_id: IdType,
+    pub status: String,
+    pub flight_start_ts: i64, // 0 means no lower bound
+    pub flight_end_ts: i64,   // 0 means no upper bound
 }
 
-impl Campaign {
-    pub fn html_debug(&self) -> String {
-        format!(
-            r#"id={}</br>name={}</br>package_id=<a href="/admin/store/package/{}">{}</a>"#,
-            self.id, self.name, self.package_id, self.package_id
-        )
-    }
-}
-
-#[derive(Debug, Default, Clone, FromRow, MysqlObject, StorableRaw)]
+#[derive(Debug, Default, Clone, FromRow, DbObject, StorableRaw, Serialize, Deserialize)]
 pub struct Package {
     pub id: IdType,
     pub name: String,
 }
 
-#[derive(Debug, Default, Clone, FromRow, MysqlObject, StorableRaw)]
+#[derive(Debug, Default, Clone, FromRow, DbObject, StorableRaw, Serialize, Deserialize)]
 pub struct Pad {
     pub id: IdType,
     pub name: String,
 }
 
-#[derive(Debug, Default, Clone, FromRow, MysqlObject, StorableRaw)]
+#[derive(Debug, Default, Clone, FromRow, DbObject, StorableRaw, Serialize, Deserialize)]
 pub struct PadRelation {
     pub id: IdType,
+    #[fk(table = "pad")]
     pub pad_id: IdType,
+    #[fk(table = "pad")]
     pub parent_pad_id: IdType,
 }
 
-#[derive(Debug, Default, Clone, FromRow, MysqlObject, StorableRaw)]
+#[derive(Debug, Default, Clone, FromRow, DbObject, StorableRaw, Serialize, Deserialize)]
 pub struct TargetingPad {
     pub id: IdType,
+    #[fk(table_from = "object_type")]
     pub object_id: IdType,
     pub object_type: String,
+    #[fk(table = "pad")]
+    pub pad_id: IdType,
     pub positive: bool,
 }
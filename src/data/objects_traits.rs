@@ -5,19 +5,71 @@ use crate::data::objects;
 use mysql_cdc::events::row_events::row_data::RowData;
 use std::collections::HashMap;
 
-pub trait MysqlObject {
+// field referencing an object in another table, declared with #[fk(...)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ForeignKey {
+    pub field: &'static str,
+    pub target: FkTarget,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FkTarget {
+    Table(&'static str),
+    TableFrom(&'static str), // table name is stored in this field of the same object
+}
+
+pub trait DbObject {
     fn table<'life>() -> &'life str
     where
         Self: Sized;
+    // struct fields in declaration order
+    fn fields() -> &'static [&'static str]
+    where
+        Self: Sized;
+    fn foreign_keys() -> &'static [ForeignKey]
+    where
+        Self: Sized;
+    // (table, id) of every foreign key, same order as foreign_keys()
+    fn references(&self) -> Vec<(&str, objects::IdType)>;
+    // mysql binlog row, fields_map is per table
     fn from_slave(row_data: &RowData, fields_map: &HashMap<String, FieldMapping>) -> Self
     where
         Self: Sized;
+    // row of text values as postgres sends them; missing and null columns are defaults
+    fn from_text(values: &[Option<String>], mapping: &FieldMapping) -> Self
+    where
+        Self: Sized;
 }
 
 pub trait StorableRaw {
     fn get_id(&self) -> objects::IdType;
+    // bytes owned outside of the struct itself, summed over fields
+    fn heap_size(&self) -> usize;
+}
+
+// estimate for memory accounting, not exact allocator usage
+pub trait HeapSize {
+    fn heap_size(&self) -> usize;
+}
+
+impl HeapSize for String {
+    fn heap_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+macro_rules! no_heap {
+    ($($t:ty),*) => {
+        $(impl HeapSize for $t {
+            fn heap_size(&self) -> usize {
+                0
+            }
+        })*
+    };
 }
 
+no_heap!(bool, i32, i64, u32, u64);
+
 pub trait Storable {
     fn insert(self, store: &mut store::Store);
     fn update(self, store: &mut store::Store, old: Option<Self>)
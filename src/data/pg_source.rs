@@ -0,0 +1,554 @@
+use crate::config;
+use crate::data::history::EventOrigin;
+use crate::data::objects::IdType;
+use crate::data::registry::{self, ObjectType};
+use crate::data::slave::FieldMapping;
+use crate::data::source::{self, ChangeSource, ChangeStream, SourceEvent, SourceObject};
+use crate::data::updater::EventType;
+
+use logging_timer::stime;
+use postgres::{Client, NoTls, SimpleQueryMessage};
+use std::collections::HashMap;
+use std::error::Error;
+use std::thread::sleep;
+use std::time::Duration;
+
+// changes asked from the slot per poll, whole transactions are returned anyway
+const BATCH_CHANGES: i32 = 1000;
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+// postgres timestamps count microseconds from 2000-01-01
+const PG_EPOCH_OFFSET_SEC: i64 = 946_684_800;
+
+// postgres backend: select for the initial load, a pgoutput logical replication slot
+// for changes; positions are slot LSNs in the usual X/Y form, the slot is advanced
+// only up to what the previous poll returned, which the updater applied by then
+pub struct PgSource {
+    conf: config::PgSource,
+    tables: Vec<String>,
+}
+
+struct PgStream {
+    client: Client,
+    slot: String,
+    publication: String,
+    advance_to: Option<u64>, // end of the last returned transaction, applied by the next poll
+    decoder: PgDecoder,
+}
+
+// pgoutput protocol version 1 messages to source events
+#[derive(Default)]
+struct PgDecoder {
+    tables: Vec<String>,
+    relations: HashMap<u32, Relation>,
+    origin: EventOrigin,
+}
+
+struct Relation {
+    name: String,
+    object_type: Option<ObjectType>, // None for tables the store doesn't keep
+    mapping: FieldMapping,
+}
+
+impl PgSource {
+    pub fn new(conf: &config::PgSource, tables: &[String]) -> Self {
+        PgSource {
+            conf: conf.clone(),
+            tables: tables.to_vec(),
+        }
+    }
+
+    fn connect(&self) -> Result<Client, postgres::Error> {
+        let db = &self.conf.db;
+        postgres::Config::new()
+            .host(&db.host)
+            .port(db.port)
+            .user(&db.username)
+            .password(&db.password)
+            .dbname(&db.db_name)
+            .connect(NoTls)
+    }
+}
+
+impl ChangeSource for PgSource {
+    fn name(&self) -> String {
+        let db = &self.conf.db;
+        format!(
+            "postgres://{}:{}/{}?slot={}",
+            db.host, db.port, db.db_name, self.conf.slot
+        )
+    }
+
+    // creates the slot on the first start, the initial load is taken after that
+    fn position(&self) -> Result<Option<String>, Box<dyn Error>> {
+        let mut client = self.connect()?;
+        if let Some(lsn) = slot_lsn(&mut client, &self.conf.slot)? {
+            return Ok(Some(lsn));
+        }
+        let row = client.query_one(
+            "SELECT lsn::text FROM pg_create_logical_replication_slot($1, 'pgoutput')",
+            &[&self.conf.slot],
+        )?;
+        log::info!("replication slot {} created", self.conf.slot);
+        Ok(Some(row.get(0)))
+    }
+
+    // the slot is advanced past what the published store has, a snapshot of it may be
+    // behind; nothing before the slot can be read again
+    fn can_resume(&self, position: &str) -> Result<bool, Box<dyn Error>> {
+        let mut client = self.connect()?;
+        Ok(match slot_lsn(&mut client, &self.conf.slot)? {
+            Some(slot_position) => parse_lsn(&slot_position)? <= parse_lsn(position)?,
+            None => false,
+        })
+    }
+
+    fn tables(&self) -> Vec<&'static str> {
+        source::source_tables(&self.tables)
+    }
+
+    #[stime("info")]
+    fn load_table(
+        &self,
+        table: &str,
+        chunk_size: usize,
+        f: &mut dyn FnMut(Vec<SourceObject>),
+    ) -> Result<(), Box<dyn Error>> {
+        let object_type = registry::find(table).ok_or(format!("unknown table {}", table))?;
+        let mut client = self.connect()?;
+        load_table(&mut client, &object_type, chunk_size, f)
+    }
+
+    fn stream(&self, position: Option<String>) -> Result<Box<dyn ChangeStream>, Box<dyn Error>> {
+        let mut client = self.connect()?;
+        let slot_position = slot_lsn(&mut client, &self.conf.slot)?
+            .ok_or(format!("replication slot {} doesn't exist", self.conf.slot))?;
+        // the slot can only move forward, what is behind it can't be read again
+        let advance_to = match position {
+            Some(position) => {
+                let (position, slot_position) = (parse_lsn(&position)?, parse_lsn(&slot_position)?);
+                if slot_position > position {
+                    return Err(format!(
+                        "slot {} is at {}, past {}: changes in between are lost",
+                        self.conf.slot,
+                        format_lsn(slot_position),
+                        format_lsn(position)
+                    )
+                    .into());
+                }
+                Some(position).filter(|position| *position > slot_position)
+            }
+            None => None,
+        };
+        Ok(Box::new(PgStream {
+            client,
+            slot: self.conf.slot.clone(),
+            publication: self.conf.publication.clone(),
+            advance_to,
+            decoder: PgDecoder {
+                tables: self.tables.clone(),
+                ..Default::default()
+            },
+        }))
+    }
+}
+
+impl ChangeStream for PgStream {
+    fn next_events(&mut self) -> Result<Vec<SourceEvent>, Box<dyn Error>> {
+        if let Some(lsn) = self.advance_to {
+            self.client.query(
+                "SELECT pg_replication_slot_advance($1, $2::text::pg_lsn)",
+                &[&self.slot, &format_lsn(lsn)],
+            )?;
+            self.advance_to = None;
+        }
+        let rows = self.client.query(
+            "SELECT data FROM pg_logical_slot_peek_binary_changes($1, NULL, $2, \
+             'proto_version', '1', 'publication_names', $3)",
+            &[&self.slot, &BATCH_CHANGES, &self.publication],
+        )?;
+
+        let mut events = Vec::new();
+        for row in rows.iter() {
+            let data: &[u8] = row.get(0);
+            self.decoder.decode(data, &mut events)?;
+        }
+        if let Some(SourceEvent::Commit { position }) = events
+            .iter()
+            .rev()
+            .find(|event| matches!(event, SourceEvent::Commit { .. }))
+        {
+            self.advance_to = Some(parse_lsn(position)?);
+        }
+        if events.is_empty() {
+            sleep(POLL_INTERVAL);
+        }
+        Ok(events)
+    }
+}
+
+impl PgDecoder {
+    fn decode(&mut self, data: &[u8], events: &mut Vec<SourceEvent>) -> Result<(), String> {
+        let mut msg = Reader { data, pos: 0 };
+        match msg.u8()? {
+            b'B' => {
+                let final_lsn = msg.u64()?;
+                let commit_ts = msg.i64()?;
+                self.origin = EventOrigin {
+                    gtid: Some(format_lsn(final_lsn)),
+                    binlog_ts: (commit_ts / 1_000_000 + PG_EPOCH_OFFSET_SEC).max(0) as u64,
+                };
+            }
+            b'C' => {
+                let _flags = msg.u8()?;
+                let _commit_lsn = msg.u64()?;
+                let end_lsn = msg.u64()?;
+                events.push(SourceEvent::Commit {
+                    position: format_lsn(end_lsn),
+                });
+            }
+            b'R' => {
+                let relid = msg.u32()?;
+                let _namespace = msg.cstr()?;
+                let name = msg.cstr()?;
+                let _replica_identity = msg.u8()?;
+                let columns = msg.i16()?;
+                let mut mapping = FieldMapping::new();
+                for pos in 0..columns.max(0) as usize {
+                    let _flags = msg.u8()?;
+                    mapping.insert(msg.cstr()?, pos);
+                    let _type_oid = msg.u32()?;
+                    let _type_modifier = msg.i32()?;
+                }
+                let relation = Relation {
+                    object_type: registry::find(&name)
+                        .filter(|_| source::has_table(&self.tables, &name)),
+                    name,
+                    mapping,
+                };
+                self.relations.insert(relid, relation);
+            }
+            b'I' => {
+                let relid = msg.u32()?;
+                msg.expect(b'N')?;
+                let (new, _) = msg.tuple()?;
+                self.push_change(events, relid, EventType::Insert, new, None)?;
+            }
+            b'U' => {
+                let relid = msg.u32()?;
+                // old values come with REPLICA IDENTITY FULL or a changed key only
+                let (old, full_old) = match msg.u8()? {
+                    kind @ (b'K' | b'O') => {
+                        let (old, unchanged) = msg.tuple()?;
+                        msg.expect(b'N')?;
+                        (Some(old), kind == b'O' && unchanged.is_empty())
+                    }
+                    b'N' => (None, false),
+                    other => return Err(format!("unexpected tuple type {}", other)),
+                };
+                let (mut new, unchanged) = msg.tuple()?;
+                // unchanged toasted values aren't sent, only the full old row has them
+                if !unchanged.is_empty() && !full_old {
+                    return Err(format!(
+                        "update of {} without toasted column {}, set REPLICA IDENTITY FULL",
+                        self.relation_name(relid),
+                        unchanged[0]
+                    ));
+                }
+                if let Some(old) = old.as_ref() {
+                    for pos in unchanged {
+                        new[pos] = old.get(pos).cloned().flatten();
+                    }
+                }
+                self.push_change(events, relid, EventType::Update, new, old)?;
+            }
+            b'D' => {
+                let relid = msg.u32()?;
+                let _key_or_old = msg.u8()?;
+                let (old, _) = msg.tuple()?;
+                self.push_change(events, relid, EventType::Delete, old, None)?;
+            }
+            b'T' => log::error!("truncate isn't replicated, run a full reload"),
+            other => log::trace!("ignore pgoutput message '{}'", other as char),
+        }
+        Ok(())
+    }
+
+    fn relation_name(&self, relid: u32) -> String {
+        self.relations
+            .get(&relid)
+            .map_or(format!("relation {}", relid), |r| r.name.clone())
+    }
+
+    fn push_change(
+        &self,
+        events: &mut Vec<SourceEvent>,
+        relid: u32,
+        ev_type: EventType,
+        values: Vec<Option<String>>,
+        old: Option<Vec<Option<String>>>,
+    ) -> Result<(), String> {
+        let relation = self
+            .relations
+            .get(&relid)
+            .ok_or(format!("change of unknown relation {}", relid))?;
+        let object_type = match relation.object_type {
+            Some(object_type) => object_type,
+            None => {
+                log::trace!("ignore change of {}", relation.name);
+                return Ok(());
+            }
+        };
+        events.push(SourceEvent::Change {
+            ev_type,
+            object: (object_type.from_text)(&values, &relation.mapping),
+            old: old.map(|old| (object_type.from_text)(&old, &relation.mapping)),
+            origin: self.origin.clone(),
+        });
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], String> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or("truncated pgoutput message")?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn i16(&mut self) -> Result<i16, String> {
+        Ok(i16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(i32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i64(&mut self) -> Result<i64, String> {
+        Ok(i64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn cstr(&mut self) -> Result<String, String> {
+        let len = self.data[self.pos..]
+            .iter()
+            .position(|b| *b == 0)
+            .ok_or("unterminated string in pgoutput message")?;
+        let value = String::from_utf8_lossy(self.take(len)?).to_string();
+        self.pos += 1;
+        Ok(value)
+    }
+
+    fn expect(&mut self, tag: u8) -> Result<(), String> {
+        match self.u8()? {
+            got if got == tag => Ok(()),
+            got => Err(format!("expected '{}', got '{}'", tag as char, got as char)),
+        }
+    }
+
+    // unchanged toasted values aren't sent, they come as nulls and their positions
+    fn tuple(&mut self) -> Result<(Vec<Option<String>>, Vec<usize>), String> {
+        let columns = self.i16()?;
+        let mut values = Vec::new();
+        let mut unchanged = Vec::new();
+        for pos in 0..columns.max(0) as usize {
+            match self.u8()? {
+                b't' => {
+                    let len = self.i32()?.max(0) as usize;
+                    values.push(Some(String::from_utf8_lossy(self.take(len)?).to_string()));
+                }
+                b'n' => values.push(None),
+                b'u' => {
+                    values.push(None);
+                    unchanged.push(pos);
+                }
+                other => return Err(format!("unexpected column kind '{}'", other as char)),
+            }
+        }
+        Ok((values, unchanged))
+    }
+}
+
+fn slot_lsn(client: &mut Client, slot: &str) -> Result<Option<String>, postgres::Error> {
+    let row = client.query_opt(
+        "SELECT confirmed_flush_lsn::text FROM pg_replication_slots WHERE slot_name = $1",
+        &[&slot],
+    )?;
+    Ok(row.and_then(|row| row.get(0)))
+}
+
+// text protocol, every column comes as a string whatever its type; pages by id
+// like the mysql load, the id bound is an integer so it is formatted in
+fn load_table(
+    client: &mut Client,
+    object_type: &ObjectType,
+    chunk_size: usize,
+    f: &mut dyn FnMut(Vec<SourceObject>),
+) -> Result<(), Box<dyn Error>> {
+    let mut mapping: Option<FieldMapping> = None;
+    let mut last_id = IdType::MIN;
+    loop {
+        let messages = client.simple_query(&format!(
+            "SELECT * FROM {} WHERE id > {} ORDER BY id LIMIT {}",
+            object_type.table, last_id, chunk_size
+        ))?;
+        let mut objects = Vec::with_capacity(chunk_size);
+        for message in messages.iter() {
+            let row = match message {
+                SimpleQueryMessage::Row(row) => row,
+                _ => continue,
+            };
+            let mapping = mapping.get_or_insert_with(|| {
+                row.columns()
+                    .iter()
+                    .enumerate()
+                    .map(|(pos, column)| (column.name().to_string(), pos))
+                    .collect()
+            });
+            let values = (0..row.len())
+                .map(|i| row.get(i).map(String::from))
+                .collect::<Vec<_>>();
+            objects.push((object_type.from_text)(&values, mapping));
+        }
+        let fetched = objects.len();
+        match objects.last() {
+            Some(last) => last_id = last.id(),
+            None => break,
+        }
+        f(objects);
+        if fetched < chunk_size {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn parse_lsn(lsn: &str) -> Result<u64, String> {
+    let (hi, lo) = lsn.split_once('/').ok_or(format!("bad lsn '{}'", lsn))?;
+    let part =
+        |s: &str| u64::from_str_radix(s, 16).map_err(|e| format!("bad lsn '{}': {}", lsn, e));
+    Ok(part(hi)? << 32 | part(lo)?)
+}
+
+fn format_lsn(lsn: u64) -> String {
+    format!("{:X}/{:X}", lsn >> 32, lsn & 0xffff_ffff)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cstr(buf: &mut Vec<u8>, s: &str) {
+        buf.extend_from_slice(s.as_bytes());
+        buf.push(0);
+    }
+
+    // "~" stands for an unchanged toasted value
+    fn tuple(buf: &mut Vec<u8>, values: &[Option<&str>]) {
+        buf.extend_from_slice(&(values.len() as i16).to_be_bytes());
+        for value in values {
+            match value {
+                Some("~") => buf.push(b'u'),
+                Some(value) => {
+                    buf.push(b't');
+                    buf.extend_from_slice(&(value.len() as i32).to_be_bytes());
+                    buf.extend_from_slice(value.as_bytes());
+                }
+                None => buf.push(b'n'),
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut relation = vec![b'R'];
+        relation.extend_from_slice(&16384u32.to_be_bytes());
+        cstr(&mut relation, "public");
+        cstr(&mut relation, "pad");
+        relation.push(b'd');
+        relation.extend_from_slice(&2i16.to_be_bytes());
+        for column in ["name", "id"] {
+            relation.push(0);
+            cstr(&mut relation, column);
+            relation.extend_from_slice(&25u32.to_be_bytes());
+            relation.extend_from_slice(&(-1i32).to_be_bytes());
+        }
+
+        let mut begin = vec![b'B'];
+        begin.extend_from_slice(&0x1_0000_0010u64.to_be_bytes());
+        begin.extend_from_slice(&1_000_000i64.to_be_bytes());
+        begin.extend_from_slice(&700u32.to_be_bytes());
+
+        let mut update = vec![b'U'];
+        update.extend_from_slice(&16384u32.to_be_bytes());
+        update.push(b'O');
+        tuple(&mut update, &[Some("old"), Some("3")]);
+        update.push(b'N');
+        tuple(&mut update, &[Some("new"), Some("3")]);
+
+        let mut commit = vec![b'C', 0];
+        commit.extend_from_slice(&0x1_0000_0010u64.to_be_bytes());
+        commit.extend_from_slice(&0x1_0000_0020u64.to_be_bytes());
+        commit.extend_from_slice(&1_000_000i64.to_be_bytes());
+
+        let mut decoder = PgDecoder::default();
+        let mut events = Vec::new();
+        for message in [relation, begin, update, commit] {
+            decoder.decode(&message, &mut events).unwrap();
+        }
+        assert_eq!(2, events.len());
+        match &events[0] {
+            SourceEvent::Change {
+                ev_type: EventType::Update,
+                object: SourceObject::Pad(new),
+                old: Some(SourceObject::Pad(old)),
+                origin,
+            } => {
+                assert_eq!(
+                    (3, "new", "old"),
+                    (new.id, new.name.as_str(), old.name.as_str())
+                );
+                assert_eq!(PG_EPOCH_OFFSET_SEC as u64 + 1, origin.binlog_ts);
+                assert_eq!(Some("1/10"), origin.gtid.as_deref());
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(&events[1], SourceEvent::Commit { position } if position == "1/20"));
+        assert_eq!(0x1_0000_0020, parse_lsn("1/20").unwrap());
+
+        // an unchanged toasted name is taken from the full old row, and refused without it
+        let update = |old_kind: u8| {
+            let mut update = vec![b'U'];
+            update.extend_from_slice(&16384u32.to_be_bytes());
+            update.push(old_kind);
+            tuple(&mut update, &[Some("large"), Some("3")]);
+            update.push(b'N');
+            tuple(&mut update, &[Some("~"), Some("3")]);
+            update
+        };
+        events.clear();
+        decoder.decode(&update(b'O'), &mut events).unwrap();
+        assert!(matches!(&events[0], SourceEvent::Change {
+            object: SourceObject::Pad(new), ..
+        } if new.name == "large"));
+        assert!(decoder.decode(&update(b'K'), &mut events).is_err());
+    }
+}
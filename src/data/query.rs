@@ -0,0 +1,170 @@
+use crate::data::objects::IdType;
+use crate::data::raw_storage::Storage;
+use crate::data::registry::ObjectType;
+use serde::Serialize;
+use serde_json::Value;
+use std::cmp::Ordering;
+
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+pub const MAX_PAGE_SIZE: usize = 10000;
+
+// admin listing of one table, evaluated against serialized objects
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct ObjectQuery {
+    pub filters: Vec<(String, String)>, // substring match for strings, exact for the rest
+    pub sort: Option<String>,           // field name, '-' prefix for descending order
+    pub offset: usize,
+    pub limit: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct QueryResult {
+    pub total: usize, // matched objects before paging
+    pub ids: Vec<IdType>,
+    pub objects: Vec<Value>,
+}
+
+impl Default for ObjectQuery {
+    fn default() -> Self {
+        ObjectQuery {
+            filters: Vec::new(),
+            sort: None,
+            offset: 0,
+            limit: DEFAULT_PAGE_SIZE,
+        }
+    }
+}
+
+impl ObjectQuery {
+    pub fn sort_field(&self) -> Option<(&str, bool)> {
+        self.sort.as_ref().map(|sort| match sort.strip_prefix('-') {
+            Some(field) => (field, true),
+            None => (sort.as_str(), false),
+        })
+    }
+}
+
+pub fn run(object_type: &ObjectType, storage: &Storage, query: &ObjectQuery) -> QueryResult {
+    let ids = (object_type.list)(storage);
+
+    // ids are sorted already, don't serialize the whole table for a plain page
+    if query.filters.is_empty() && query.sort.is_none() {
+        let page = ids
+            .iter()
+            .skip(query.offset)
+            .take(query.limit)
+            .cloned()
+            .collect::<Vec<_>>();
+        return QueryResult {
+            total: ids.len(),
+            objects: page
+                .iter()
+                .filter_map(|id| (object_type.to_json)(storage, *id))
+                .collect(),
+            ids: page,
+        };
+    }
+
+    let mut matched = ids
+        .into_iter()
+        .filter_map(|id| (object_type.to_json)(storage, id).map(|obj| (id, obj)))
+        .filter(|(_, obj)| {
+            query
+                .filters
+                .iter()
+                .all(|(field, expected)| matches(obj, field, expected))
+        })
+        .collect::<Vec<_>>();
+    if let Some((field, desc)) = query.sort_field() {
+        // stable sort keeps id order between equal values
+        matched.sort_by(|(_, a), (_, b)| {
+            let ord = compare(a.get(field), b.get(field));
+            match desc {
+                true => ord.reverse(),
+                false => ord,
+            }
+        });
+    }
+
+    let total = matched.len();
+    let (ids, objects) = matched
+        .into_iter()
+        .skip(query.offset)
+        .take(query.limit)
+        .unzip();
+    QueryResult {
+        total,
+        ids,
+        objects,
+    }
+}
+
+fn matches(object: &Value, field: &str, expected: &str) -> bool {
+    match object.get(field) {
+        Some(Value::String(value)) => value.to_lowercase().contains(&expected.to_lowercase()),
+        Some(value) => serde_json::from_str::<Value>(expected).is_ok_and(|e| e == *value),
+        None => false,
+    }
+}
+
+fn compare(a: Option<&Value>, b: Option<&Value>) -> Ordering {
+    match (a, b) {
+        (Some(Value::Number(a)), Some(Value::Number(b))) => a
+            .as_f64()
+            .partial_cmp(&b.as_f64())
+            .unwrap_or(Ordering::Equal),
+        (Some(Value::String(a)), Some(Value::String(b))) => a.cmp(b),
+        (Some(Value::Bool(a)), Some(Value::Bool(b))) => a.cmp(b),
+        _ => Ordering::Equal,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::objects::Campaign;
+    use crate::data::registry;
+
+    #[test]
+    fn test_query() {
+        let mut storage = Storage::default();
+        for (id, name, package_id) in [
+            (1, "Red", 3),
+            (2, "blue", 3),
+            (3, "dark red", 4),
+            (4, "red", 3),
+        ] {
+            storage.update(Campaign {
+                id,
+                name: name.to_string(),
+                package_id,
+                ..Default::default()
+            });
+        }
+        let campaign = registry::find("campaign").unwrap();
+
+        let query = ObjectQuery {
+            filters: vec![
+                (String::from("name"), String::from("red")),
+                (String::from("package_id"), String::from("3")),
+            ],
+            sort: Some(String::from("-id")),
+            ..Default::default()
+        };
+        let result = run(&campaign, &storage, &query);
+        assert_eq!((2, vec![4, 1]), (result.total, result.ids));
+
+        let query = ObjectQuery {
+            sort: Some(String::from("name")),
+            offset: 1,
+            limit: 2,
+            ..Default::default()
+        };
+        let result = run(&campaign, &storage, &query);
+        assert_eq!((4, vec![2, 3]), (result.total, result.ids));
+        assert_eq!("blue", result.objects[0]["name"]);
+
+        let result = run(&campaign, &storage, &ObjectQuery::default());
+        assert_eq!((4, vec![1, 2, 3, 4]), (result.total, result.ids));
+    }
+}
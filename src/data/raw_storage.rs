@@ -1,54 +1,180 @@
+use crate::data::mapped::MappedTables;
+use crate::data::memory::TableMemory;
 use crate::data::objects::IdType;
-use crate::data::objects_traits::{MysqlObject, StorableRaw};
+use crate::data::objects_traits::{DbObject, StorableRaw};
+use serde::de::DeserializeOwned;
 use std::any::Any;
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::mem::size_of;
+use std::sync::Arc;
 
+// heap objects, optionally layered over a mapped base: then data holds only what changed since
 #[derive(Default)]
 pub struct Storage {
     data: HashMap<&'static str, HashMap<IdType, Box<dyn Any + Sync + Send>>>,
+    base: Option<Arc<MappedTables>>,
+    deleted: HashMap<&'static str, HashSet<IdType>>, // base objects deleted since
 }
 
 impl Storage {
-    pub fn update<T: MysqlObject + StorableRaw + Sync + Send + 'static>(&mut self, obj: T) {
+    pub fn with_base(base: Arc<MappedTables>) -> Self {
+        Storage {
+            base: Some(base),
+            ..Default::default()
+        }
+    }
+
+    pub fn update<T: DbObject + StorableRaw + Sync + Send + 'static>(&mut self, obj: T) {
+        if let Some(deleted) = self.deleted.get_mut(T::table()) {
+            deleted.remove(&obj.get_id());
+        }
         self.data
             .entry(T::table())
             .or_default()
             .insert(obj.get_id(), Box::new(obj));
     }
-    pub fn delete<T: MysqlObject + StorableRaw + Sync + Send + 'static>(&mut self, obj: T) {
+    pub fn delete<T: DbObject + StorableRaw + Sync + Send + 'static>(&mut self, obj: T) {
         self.data
             .entry(T::table())
             .or_default()
             .remove(&obj.get_id());
+        if self
+            .base
+            .as_ref()
+            .is_some_and(|base| base.contains(T::table(), obj.get_id()))
+        {
+            self.deleted
+                .entry(T::table())
+                .or_default()
+                .insert(obj.get_id());
+        }
     }
 
     #[allow(dead_code)]
-    pub fn get<T: StorableRaw + MysqlObject + 'static>(&self, id: IdType) -> &T {
-        self.data
+    pub fn get<T: StorableRaw + DbObject + DeserializeOwned + Clone + 'static>(
+        &self,
+        id: IdType,
+    ) -> Cow<'_, T> {
+        self.try_get(id).unwrap()
+    }
+
+    // borrowed from heap, decoded from the mapped base otherwise
+    pub fn try_get<T: StorableRaw + DbObject + DeserializeOwned + Clone + 'static>(
+        &self,
+        id: IdType,
+    ) -> Option<Cow<'_, T>> {
+        if let Some(obj) = self
+            .data
             .get(T::table())
-            .unwrap()
-            .get(&id)
-            .unwrap()
-            .downcast_ref::<T>()
-            .unwrap()
+            .and_then(|objects| objects.get(&id))
+        {
+            return obj.downcast_ref::<T>().map(Cow::Borrowed);
+        }
+        if self.is_deleted(T::table(), id) {
+            return None;
+        }
+        self.base.as_ref()?.get::<T>(id).map(Cow::Owned)
     }
 
-    #[allow(dead_code)]
-    pub fn try_get<T: StorableRaw + MysqlObject + 'static>(&self, id: IdType) -> Option<&T> {
-        match self.data.get(T::table()) {
-            Some(objects) => match objects.get(&id) {
-                Some(obj) => obj.downcast_ref::<T>(),
-                None => None,
-            },
-            None => None,
+    pub fn iter<T: StorableRaw + DbObject + DeserializeOwned + Clone + 'static>(
+        &self,
+    ) -> impl Iterator<Item = Cow<'_, T>> {
+        let objects = self.data.get(T::table());
+        let heap = objects
+            .into_iter()
+            .flat_map(|objects| objects.values())
+            .filter_map(|obj| obj.downcast_ref::<T>())
+            .map(Cow::Borrowed);
+        let base = self
+            .base
+            .iter()
+            .flat_map(|base| base.iter::<T>())
+            .filter(move |obj| {
+                let id = obj.get_id();
+                !objects.is_some_and(|objects| objects.contains_key(&id))
+                    && !self.is_deleted(T::table(), id)
+            })
+            .map(Cow::Owned);
+        heap.chain(base)
+    }
+
+    // objects per table, sorted by table name
+    pub fn counts(&self) -> Vec<(&'static str, usize)> {
+        let mut counts = self
+            .data
+            .iter()
+            .map(|(table, objects)| (*table, self.count(table, objects)))
+            .collect::<Vec<_>>();
+        if let Some(base) = self.base.as_ref() {
+            for table in base.tables() {
+                if !self.data.contains_key(table) {
+                    counts.push((table, self.count(table, &HashMap::new())));
+                }
+            }
+        }
+        counts.sort();
+        counts
+    }
+
+    pub fn list<T: DbObject>(&self) -> Vec<IdType> {
+        let objects = self.data.get(T::table());
+        let mut ids = objects
+            .into_iter()
+            .flat_map(|objects| objects.keys().copied())
+            .collect::<Vec<_>>();
+        if let Some(base) = self.base.as_ref() {
+            ids.extend(base.ids(T::table()).filter(|id| {
+                !objects.is_some_and(|objects| objects.contains_key(id))
+                    && !self.is_deleted(T::table(), *id)
+            }));
         }
+        ids
     }
 
-    pub fn list<T: MysqlObject>(&self) -> Vec<IdType> {
-        match self.data.get(T::table()) {
-            Some(objects) => Vec::from_iter(objects.iter().map(|(x, _)| *x).collect::<Vec<_>>()),
+    pub fn memory<T: StorableRaw + DbObject + 'static>(&self) -> TableMemory {
+        let objects = self.data.get(T::table());
+        let slot = size_of::<IdType>() + size_of::<Box<dyn Any + Sync + Send>>() + 1;
+        let heap_bytes = objects.map_or(0, |objects| {
+            objects.capacity() * slot
+                + objects
+                    .values()
+                    .filter_map(|obj| obj.downcast_ref::<T>())
+                    .map(|obj| size_of::<T>() + obj.heap_size())
+                    .sum::<usize>()
+        });
+        let deleted_bytes = self
+            .deleted
+            .get(T::table())
+            .map_or(0, |deleted| deleted.capacity() * (size_of::<IdType>() + 1));
+        TableMemory {
+            table: T::table().to_string(),
+            objects: self.count(T::table(), objects.unwrap_or(&HashMap::new())),
+            heap_bytes: heap_bytes + deleted_bytes,
+            mapped_bytes: self
+                .base
+                .as_ref()
+                .map_or(0, |base| base.table_size(T::table())),
+        }
+    }
+
+    fn is_deleted(&self, table: &str, id: IdType) -> bool {
+        self.deleted
+            .get(table)
+            .is_some_and(|deleted| deleted.contains(&id))
+    }
 
-            None => Vec::default(),
+    fn count(&self, table: &str, objects: &HashMap<IdType, Box<dyn Any + Sync + Send>>) -> usize {
+        match self.base.as_ref() {
+            Some(base) => {
+                let deleted = self.deleted.get(table).map_or(0, |d| d.len());
+                let added = objects
+                    .keys()
+                    .filter(|id| !base.contains(table, **id))
+                    .count();
+                base.count(table) - deleted + added
+            }
+            None => objects.len(),
         }
     }
 }
@@ -0,0 +1,246 @@
+use crate::data::memory::TableMemory;
+use crate::data::objects::{Campaign, IdType, Package, Pad, PadRelation, TargetingPad};
+use crate::data::objects_traits::{DbObject, FkTarget, ForeignKey, StorableRaw};
+use crate::data::raw_storage::Storage;
+use crate::data::slave::FieldMapping;
+use crate::data::source::SourceObject;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+// type-erased access to a stored object type, for admin pages and api
+#[derive(Clone, Copy)]
+pub struct ObjectType {
+    pub table: &'static str,
+    pub fields: &'static [&'static str],
+    pub foreign_keys: &'static [ForeignKey],
+    pub list: fn(&Storage) -> Vec<IdType>,
+    pub to_json: fn(&Storage, IdType) -> Option<Value>,
+    pub memory: fn(&Storage) -> TableMemory,
+    pub from_json: fn(Value) -> serde_json::Result<SourceObject>,
+    pub from_text: fn(&[Option<String>], &FieldMapping) -> SourceObject,
+    pub backrefs: fn(&Storage, &str, IdType) -> Vec<Backref>,
+}
+
+// resolved foreign key value
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct Link {
+    pub field: &'static str,
+    pub table: String,
+    pub id: IdType,
+}
+
+// object referencing another one through `field`
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct Backref {
+    pub table: &'static str,
+    pub field: &'static str,
+    pub id: IdType,
+    pub links: Vec<Link>, // all forward links of the referencing object
+}
+
+impl ObjectType {
+    fn of<T>() -> Self
+    where
+        T: DbObject
+            + StorableRaw
+            + Serialize
+            + DeserializeOwned
+            + Into<SourceObject>
+            + Clone
+            + 'static,
+    {
+        ObjectType {
+            table: T::table(),
+            fields: T::fields(),
+            foreign_keys: T::foreign_keys(),
+            list: |storage| {
+                let mut ids = storage.list::<T>();
+                ids.sort();
+                ids
+            },
+            to_json: |storage, id| {
+                storage
+                    .try_get::<T>(id)
+                    .and_then(|obj| serde_json::to_value(obj).ok())
+            },
+            memory: |storage| storage.memory::<T>(),
+            from_json: |value| serde_json::from_value::<T>(value).map(Into::into),
+            from_text: |values, mapping| T::from_text(values, mapping).into(),
+            backrefs: backrefs_of::<T>,
+        }
+    }
+}
+
+// every type the store keeps, in admin display order
+pub fn object_types() -> Vec<ObjectType> {
+    vec![
+        ObjectType::of::<Campaign>(),
+        ObjectType::of::<Package>(),
+        ObjectType::of::<Pad>(),
+        ObjectType::of::<PadRelation>(),
+        ObjectType::of::<TargetingPad>(),
+    ]
+}
+
+pub fn find(table: &str) -> Option<ObjectType> {
+    object_types().into_iter().find(|t| t.table == table)
+}
+
+// tables having an object with this id
+pub fn find_by_id(storage: &Storage, id: IdType) -> Vec<&'static str> {
+    object_types()
+        .into_iter()
+        .filter(|t| (t.to_json)(storage, id).is_some())
+        .map(|t| t.table)
+        .collect()
+}
+
+// foreign keys of a serialized object; zero ids and unknown tables are skipped
+pub fn forward_links(object_type: &ObjectType, object: &Value) -> Vec<Link> {
+    object_type
+        .foreign_keys
+        .iter()
+        .filter_map(|fk| {
+            let id = object.get(fk.field)?.as_i64()? as IdType;
+            let table = match fk.target {
+                FkTarget::Table(table) => table,
+                FkTarget::TableFrom(field) => object.get(field)?.as_str()?,
+            };
+            link(fk, table, id)
+        })
+        .collect()
+}
+
+fn link(fk: &ForeignKey, table: &str, id: IdType) -> Option<Link> {
+    match id != 0 && find(table).is_some() {
+        true => Some(Link {
+            field: fk.field,
+            table: table.to_string(),
+            id,
+        }),
+        false => None,
+    }
+}
+
+// objects of all types pointing to table/id; scans whole tables, admin use only
+pub fn reverse_links(storage: &Storage, table: &str, id: IdType) -> Vec<Backref> {
+    let mut backrefs = Vec::new();
+    for object_type in object_types() {
+        let may_reference = object_type.foreign_keys.iter().any(|fk| match fk.target {
+            FkTarget::Table(target) => target == table,
+            FkTarget::TableFrom(_) => true,
+        });
+        if !may_reference {
+            continue;
+        }
+        backrefs.extend((object_type.backrefs)(storage, table, id));
+    }
+    backrefs
+}
+
+// typed fields are compared, links are only built for the matching objects
+fn backrefs_of<T>(storage: &Storage, table: &str, id: IdType) -> Vec<Backref>
+where
+    T: DbObject + StorableRaw + DeserializeOwned + Clone + 'static,
+{
+    let mut backrefs = Vec::new();
+    for object in storage.iter::<T>() {
+        let references = object.references();
+        if !references.contains(&(table, id)) {
+            continue;
+        }
+        let links = T::foreign_keys()
+            .iter()
+            .zip(references.iter())
+            .filter_map(|(fk, (fk_table, fk_id))| link(fk, fk_table, *fk_id))
+            .collect::<Vec<_>>();
+        for link in links.iter().filter(|l| l.table == table && l.id == id) {
+            backrefs.push(Backref {
+                table: T::table(),
+                field: link.field,
+                id: object.get_id(),
+                links: links.clone(),
+            });
+        }
+    }
+    backrefs.sort_by_key(|b| b.id);
+    backrefs
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_links() {
+        let mut storage = Storage::default();
+        storage.update(Campaign {
+            id: 1,
+            package_id: 10,
+            ..Default::default()
+        });
+        storage.update(Package {
+            id: 10,
+            ..Default::default()
+        });
+        storage.update(PadRelation {
+            id: 5,
+            pad_id: 2,
+            parent_pad_id: 3,
+        });
+        storage.update(TargetingPad {
+            id: 7,
+            object_id: 1,
+            object_type: String::from("campaign"),
+            pad_id: 3,
+            positive: true,
+        });
+
+        assert_eq!(vec!["campaign"], find_by_id(&storage, 1));
+        assert_eq!(vec!["pad_relation"], find_by_id(&storage, 5));
+
+        let campaign = find("campaign").unwrap();
+        let links = forward_links(&campaign, &(campaign.to_json)(&storage, 1).unwrap());
+        assert_eq!(
+            vec![Link {
+                field: "package_id",
+                table: String::from("package"),
+                id: 10
+            }],
+            links
+        );
+
+        let backrefs = reverse_links(&storage, "package", 10);
+        assert_eq!(1, backrefs.len());
+        assert_eq!(
+            ("campaign", "package_id", 1),
+            (backrefs[0].table, backrefs[0].field, backrefs[0].id)
+        );
+
+        // pad 3 is the parent in a relation and a targeting rule
+        let backrefs = reverse_links(&storage, "pad", 3)
+            .iter()
+            .map(|b| (b.table, b.field, b.id))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("pad_relation", "parent_pad_id", 5),
+                ("targeting_pad", "pad_id", 7)
+            ],
+            backrefs
+        );
+
+        // polymorphic reference resolved through object_type
+        let rule = storage.try_get::<TargetingPad>(7).unwrap();
+        assert_eq!(vec![("campaign", 1), ("pad", 3)], rule.references());
+        let backrefs = reverse_links(&storage, "campaign", 1);
+        assert_eq!(
+            vec![("targeting_pad", "object_id", 7)],
+            backrefs
+                .iter()
+                .map(|b| (b.table, b.field, b.id))
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -1,15 +1,13 @@
 use crate::config;
-use crate::data::objects::{Campaign, Package, Pad};
-use crate::data::objects_traits::{MysqlObject, Storable};
-use crate::data::updater;
-use crate::data::updater::UpdaterPtr;
+use crate::data::objects::{Campaign, IdType, Package, Pad, PadRelation, TargetingPad};
+use crate::data::objects_traits::{DbObject, StorableRaw};
+use crate::data::source::SourceObject;
 
 use logging_timer::stime;
 use mysql::prelude::*;
 use mysql::*;
 use mysql_cdc::providers::mysql::gtid::gtid_set::GtidSet;
 use std::error::Error;
-use std::fmt::Debug;
 
 pub fn get_connection(db_conf: &config::DB) -> Result<PooledConn> {
     let url = format!(
@@ -37,28 +35,55 @@ pub fn get_master_gtid(db_conf: &config::DB) -> Result<Option<GtidSet>, Box<dyn
     Ok(None)
 }
 
+// one table in pages by id, see ChangeSource::load_table
 #[stime("info")]
-pub fn init(updater: &UpdaterPtr, db_conf: &config::DB) -> Result<(), Box<dyn Error>> {
+pub fn load_table(
+    db_conf: &config::DB,
+    table: &str,
+    chunk_size: usize,
+    f: &mut dyn FnMut(Vec<SourceObject>),
+) -> Result<(), Box<dyn Error>> {
     let mut conn = get_connection(db_conf)?;
 
-    init_objects::<Campaign>(updater, &mut conn)?;
-    init_objects::<Package>(updater, &mut conn)?;
-    init_objects::<Pad>(updater, &mut conn)?;
+    match table {
+        t if t == Campaign::table() => load_objects::<Campaign>(&mut conn, chunk_size, f)?,
+        t if t == Package::table() => load_objects::<Package>(&mut conn, chunk_size, f)?,
+        t if t == Pad::table() => load_objects::<Pad>(&mut conn, chunk_size, f)?,
+        t if t == PadRelation::table() => load_objects::<PadRelation>(&mut conn, chunk_size, f)?,
+        t if t == TargetingPad::table() => load_objects::<TargetingPad>(&mut conn, chunk_size, f)?,
+        _ => return Err(format!("unknown table {}", table).into()),
+    }
     Ok(())
 }
 
-// select all objects from db and store type table id
-fn init_objects<T>(updater: &UpdaterPtr, conn: &mut PooledConn) -> Result<(), mysql::Error>
+// keyset pagination, every select is a short range scan of the primary key
+fn load_objects<T>(
+    conn: &mut PooledConn,
+    chunk_size: usize,
+    f: &mut dyn FnMut(Vec<SourceObject>),
+) -> Result<(), mysql::Error>
 where
-    T: MysqlObject + FromRow + Storable + Default + Debug + Clone + Sync + Send + 'static,
+    T: DbObject + StorableRaw + FromRow + Into<SourceObject>,
 {
-    let query = format!("SELECT * FROM {}", T::table());
-
-    conn.query_map(query, |row| {
-        let object = T::from_row(row);
-        updater::apply_to_store(updater, object, None, updater::EventType::Insert);
-    })?;
-
+    let query = format!(
+        "SELECT * FROM {} WHERE id > ? ORDER BY id LIMIT {}",
+        T::table(),
+        chunk_size
+    );
+    let statement = conn.prep(query)?;
+    let mut last_id = IdType::MIN;
+    loop {
+        let objects: Vec<T> = conn.exec(&statement, (last_id,))?;
+        let fetched = objects.len();
+        match objects.last() {
+            Some(last) => last_id = last.get_id(),
+            None => break,
+        }
+        f(objects.into_iter().map(Into::into).collect());
+        if fetched < chunk_size {
+            break;
+        }
+    }
     Ok(())
 }
 
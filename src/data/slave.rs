@@ -1,21 +1,28 @@
 use crate::config;
-use crate::data::objects_traits::MysqlObject;
-use crate::data::updater::{EventType, UpdaterPtr};
-use crate::data::{objects, select, updater};
-use crate::helpers::StopChecker;
+use crate::data::history::EventOrigin;
+use crate::data::objects_traits::DbObject;
+use crate::data::source::{self, ChangeSource, ChangeStream, SourceEvent, SourceObject};
+use crate::data::updater::EventType;
+use crate::data::{objects, select};
+use crate::engine::EngineStat;
 use mysql_cdc::binlog_client::BinlogClient;
 use mysql_cdc::binlog_events::BinlogEvents;
 use mysql_cdc::binlog_options::BinlogOptions;
 use mysql_cdc::events::binlog_event::BinlogEvent;
 use mysql_cdc::events::row_events::delete_rows_event::DeleteRowsEvent;
+use mysql_cdc::events::row_events::row_data::RowData;
 use mysql_cdc::events::row_events::update_rows_event::UpdateRowsEvent;
 use mysql_cdc::events::row_events::write_rows_event::WriteRowsEvent;
 use mysql_cdc::events::table_map_event::TableMapEvent;
+use mysql_cdc::providers::mysql::gtid::gtid::Gtid;
 use mysql_cdc::providers::mysql::gtid::gtid_set::GtidSet;
 use mysql_cdc::replica_options::ReplicaOptions;
 use mysql_cdc::ssl_mode::SslMode;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::error::Error;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use std::time::Duration;
 
 #[derive(Debug, Hash, Eq, PartialEq, Deserialize, Serialize)]
@@ -44,21 +51,38 @@ impl From<&str> for SupportedTypes {
 
 pub type FieldMapping = HashMap<String, usize>;
 
-struct Context {
-    updater: UpdaterPtr,
+// mysql backend: select for the initial load, binlog replication for changes
+pub struct MysqlSource {
+    db_conf: config::DB,
+    server_id: u32, // replica id, unique among the sources
+    tables: Vec<String>,
+    stat: Arc<EngineStat>,
+}
+
+struct BinlogStream {
+    tables: Vec<String>,
     table_id_map: HashMap<u64, SupportedTypes>,
     fields_map: HashMap<String, FieldMapping>,
     slave_cli: BinlogClient,
+    stat: Arc<EngineStat>,
+    origin: EventOrigin, // gtid and timestamp of the transaction being processed
+    position: Option<GtidSet>, // transactions fully read
+    pending_gtid: Option<Gtid>, // transaction being read, until its xid
+    events: Vec<SourceEvent>,
 }
 
-fn build_slave_cli_opts(db_conf: &config::DB, gtid: Option<GtidSet>) -> ReplicaOptions {
+fn build_slave_cli_opts(
+    db_conf: &config::DB,
+    server_id: u32,
+    gtid: Option<GtidSet>,
+) -> ReplicaOptions {
     ReplicaOptions {
         hostname: db_conf.host.clone(),
         port: db_conf.port,
         username: db_conf.username.clone(),
         password: db_conf.password.clone(),
         database: Some(db_conf.db_name.clone()),
-        server_id: 65535,
+        server_id,
         blocking: false,
         ssl_mode: SslMode::Disabled,
         binlog: match gtid {
@@ -69,37 +93,92 @@ fn build_slave_cli_opts(db_conf: &config::DB, gtid: Option<GtidSet>) -> ReplicaO
     }
 }
 
-pub fn slave_loop(updater: UpdaterPtr, start_gtid: Option<GtidSet>) {
-    let lock = updater.read().expect("fail to get updater lock");
-    let (db_conf, stop_flag) = (lock.conf.db.clone(), lock.stop_flag.clone());
-    drop(lock);
-    let mut ctx = Context {
-        updater: updater.clone(),
-        table_id_map: HashMap::new(),
-        fields_map: HashMap::new(),
-        slave_cli: BinlogClient::new(build_slave_cli_opts(&db_conf, start_gtid)),
-    };
+impl MysqlSource {
+    pub fn new(
+        db_conf: &config::DB,
+        server_id: u32,
+        tables: &[String],
+        stat: Arc<EngineStat>,
+    ) -> Self {
+        MysqlSource {
+            db_conf: db_conf.clone(),
+            server_id,
+            tables: tables.to_vec(),
+            stat,
+        }
+    }
+}
 
-    fill_fields_map::<objects::Campaign>(&db_conf, &mut ctx.fields_map, false);
-    fill_fields_map::<objects::Package>(&db_conf, &mut ctx.fields_map, false);
-    fill_fields_map::<objects::Pad>(&db_conf, &mut ctx.fields_map, false);
+impl ChangeSource for MysqlSource {
+    fn name(&self) -> String {
+        format!(
+            "mysql://{}:{}/{}",
+            self.db_conf.host, self.db_conf.port, self.db_conf.db_name
+        )
+    }
 
-    let mut stop_checker = StopChecker::new(stop_flag);
+    fn position(&self) -> Result<Option<String>, Box<dyn Error>> {
+        Ok(select::get_master_gtid(&self.db_conf)?.map(|gtid| gtid.to_string()))
+    }
 
-    while !stop_checker.is_time() {
-        match ctx.slave_cli.replicate() {
-            Ok(events) => {
-                process_events(&mut ctx, events);
-            }
-            Err(e) => {
-                log::warn!("Got error from slave stream: {:?}", e)
-            }
-        }
+    fn tables(&self) -> Vec<&'static str> {
+        source::source_tables(&self.tables)
+    }
+
+    fn load_table(
+        &self,
+        table: &str,
+        chunk_size: usize,
+        f: &mut dyn FnMut(Vec<SourceObject>),
+    ) -> Result<(), Box<dyn Error>> {
+        select::load_table(&self.db_conf, table, chunk_size, f)
+    }
+
+    fn stream(&self, position: Option<String>) -> Result<Box<dyn ChangeStream>, Box<dyn Error>> {
+        let position = match position {
+            Some(gtid) => Some(
+                GtidSet::parse(gtid.as_str())
+                    .map_err(|e| format!("bad gtid set '{}': {:?}", gtid, e))?,
+            ),
+            None => None,
+        };
+        let mut stream = BinlogStream {
+            tables: self.tables.clone(),
+            table_id_map: HashMap::new(),
+            fields_map: HashMap::new(),
+            slave_cli: BinlogClient::new(build_slave_cli_opts(
+                &self.db_conf,
+                self.server_id,
+                position.clone(),
+            )),
+            stat: self.stat.clone(),
+            origin: EventOrigin::default(),
+            position,
+            pending_gtid: None,
+            events: Vec::new(),
+        };
+
+        fill_fields_map::<objects::Campaign>(&self.db_conf, &mut stream, false);
+        fill_fields_map::<objects::Package>(&self.db_conf, &mut stream, false);
+        fill_fields_map::<objects::Pad>(&self.db_conf, &mut stream, false);
+        fill_fields_map::<objects::PadRelation>(&self.db_conf, &mut stream, false);
+        fill_fields_map::<objects::TargetingPad>(&self.db_conf, &mut stream, false);
+        Ok(Box::new(stream))
+    }
+}
+
+impl ChangeStream for BinlogStream {
+    fn next_events(&mut self) -> Result<Vec<SourceEvent>, Box<dyn Error>> {
+        let events = self
+            .slave_cli
+            .replicate()
+            .map_err(|e| format!("got error from slave stream: {:?}", e))?;
+        process_events(self, events);
+        Ok(self.events.drain(..).collect())
     }
-    log::info!("slave thread finished");
 }
 
-fn process_events(ctx: &mut Context, events: BinlogEvents) {
+fn process_events(ctx: &mut BinlogStream, events: BinlogEvents) {
     for event in events {
         log::trace!("got new slave event: '{:?}'", event);
         let (header, ev_type) = match event {
@@ -109,7 +188,24 @@ fn process_events(ctx: &mut Context, events: BinlogEvents) {
                 continue;
             }
         };
+        update_stat(&ctx.stat, &ev_type);
+        if header.timestamp != 0 {
+            ctx.origin.binlog_ts = header.timestamp as u64;
+        }
         match ev_type {
+            BinlogEvent::MySqlGtidEvent(ref ev_body) => {
+                // ddl has no xid, it is complete once the next transaction starts
+                if let Some(gtid) = ctx.pending_gtid.replace(ev_body.gtid.clone()) {
+                    commit(ctx, gtid);
+                }
+                ctx.origin.gtid = Some(ev_body.gtid.to_string());
+            }
+            // ends a transaction with row changes, no need to wait for the next one
+            BinlogEvent::XidEvent(_) => {
+                if let Some(gtid) = ctx.pending_gtid.take() {
+                    commit(ctx, gtid);
+                }
+            }
             BinlogEvent::WriteRowsEvent(ref ev_body) => process_write(ctx, ev_body),
             BinlogEvent::UpdateRowsEvent(ref ev_body) => process_update(ctx, ev_body),
             BinlogEvent::DeleteRowsEvent(ref ev_body) => process_delete(ctx, ev_body),
@@ -120,81 +216,119 @@ fn process_events(ctx: &mut Context, events: BinlogEvents) {
     }
 }
 
-fn process_write(ctx: &mut Context, events: &WriteRowsEvent) {
-    for ev in events.rows.iter() {
-        if let Some(obj_type) = ctx.table_id_map.get(&events.table_id) {
-            match obj_type {
-                SupportedTypes::Campaign => {
-                    updater::apply_to_store(
-                        &ctx.updater,
-                        objects::Campaign::from_slave(ev, &ctx.fields_map),
-                        None,
-                        EventType::Insert,
-                    );
-                }
-                SupportedTypes::Package => {
-                    updater::apply_to_store(
-                        &ctx.updater,
-                        objects::Package::from_slave(ev, &ctx.fields_map),
-                        None,
-                        EventType::Insert,
-                    );
-                }
-                SupportedTypes::Pad => {
-                    updater::apply_to_store(
-                        &ctx.updater,
-                        objects::Pad::from_slave(ev, &ctx.fields_map),
-                        None,
-                        EventType::Insert,
-                    );
-                }
-                SupportedTypes::PadRelation => {
-                    updater::apply_to_store(
-                        &ctx.updater,
-                        objects::PadRelation::from_slave(ev, &ctx.fields_map),
-                        None,
-                        EventType::Insert,
-                    );
-                }
-                SupportedTypes::TargetingPad => {
-                    updater::apply_to_store(
-                        &ctx.updater,
-                        objects::TargetingPad::from_slave(ev, &ctx.fields_map),
-                        None,
-                        EventType::Insert,
-                    );
-                }
-                SupportedTypes::Unknown => {}
-            };
-        }
+fn commit(ctx: &mut BinlogStream, gtid: Gtid) {
+    let position = match ctx.position.as_mut() {
+        Some(position) => position,
+        None => return,
+    };
+    if let Err(e) = position.add_gtid(gtid) {
+        log::warn!("fail to advance replication position: {:?}", e);
+        return;
     }
+    ctx.events.push(SourceEvent::Commit {
+        position: position.to_string(),
+    });
 }
 
-fn process_update(_ctx: &mut Context, events: &UpdateRowsEvent) {
-    for _ev in events.rows.iter() {}
+fn update_stat(stat: &EngineStat, ev_type: &BinlogEvent) {
+    // same order as updater::BINLOG_EVENT_TYPES
+    let pos = match ev_type {
+        BinlogEvent::WriteRowsEvent(_) => 0,
+        BinlogEvent::UpdateRowsEvent(_) => 1,
+        BinlogEvent::DeleteRowsEvent(_) => 2,
+        BinlogEvent::TableMapEvent(_) => 3,
+        _ => 4,
+    };
+    stat.updater.binlog_events[pos].fetch_add(1, Ordering::Relaxed);
+}
+
+fn process_write(ctx: &mut BinlogStream, events: &WriteRowsEvent) {
+    for row in events.rows.iter() {
+        push_change(ctx, events.table_id, EventType::Insert, row, None);
+    }
 }
 
-fn process_delete(_ctx: &mut Context, events: &DeleteRowsEvent) {
-    for _ev in events.rows.iter() {}
+fn process_update(ctx: &mut BinlogStream, events: &UpdateRowsEvent) {
+    for row in events.rows.iter() {
+        push_change(
+            ctx,
+            events.table_id,
+            EventType::Update,
+            &row.after_update,
+            Some(&row.before_update),
+        );
+    }
+}
+
+fn process_delete(ctx: &mut BinlogStream, events: &DeleteRowsEvent) {
+    for row in events.rows.iter() {
+        push_change(ctx, events.table_id, EventType::Delete, row, None);
+    }
+}
+
+// rows of unknown tables are skipped
+fn push_change(
+    ctx: &mut BinlogStream,
+    table_id: u64,
+    ev_type: EventType,
+    row: &RowData,
+    old_row: Option<&RowData>,
+) {
+    let obj_type = match ctx.table_id_map.get(&table_id) {
+        Some(obj_type) => obj_type,
+        None => return,
+    };
+    let object = match from_slave(obj_type, row, &ctx.fields_map) {
+        Some(object) => object,
+        None => return,
+    };
+    let old = old_row.and_then(|old| from_slave(obj_type, old, &ctx.fields_map));
+    ctx.events.push(SourceEvent::Change {
+        ev_type,
+        object,
+        old,
+        origin: ctx.origin.clone(),
+    });
+}
+
+fn from_slave(
+    obj_type: &SupportedTypes,
+    row: &RowData,
+    fields_map: &HashMap<String, FieldMapping>,
+) -> Option<SourceObject> {
+    match obj_type {
+        SupportedTypes::Campaign => Some(objects::Campaign::from_slave(row, fields_map).into()),
+        SupportedTypes::Package => Some(objects::Package::from_slave(row, fields_map).into()),
+        SupportedTypes::Pad => Some(objects::Pad::from_slave(row, fields_map).into()),
+        SupportedTypes::PadRelation => {
+            Some(objects::PadRelation::from_slave(row, fields_map).into())
+        }
+        SupportedTypes::TargetingPad => {
+            Some(objects::TargetingPad::from_slave(row, fields_map).into())
+        }
+        SupportedTypes::Unknown => None,
+    }
 }
 
-fn process_table_map(ctx: &mut Context, event: &TableMapEvent) {
+fn process_table_map(ctx: &mut BinlogStream, event: &TableMapEvent) {
     if ctx.table_id_map.contains_key(&event.table_id) {
         return;
     }
-    let t = serde_json::from_str::<SupportedTypes>(
-        format!("\"{}\"", event.table_name.as_str()).as_str(),
-    )
-    .unwrap_or(SupportedTypes::Unknown);
+    let t = match source::has_table(&ctx.tables, event.table_name.as_str()) {
+        true => serde_json::from_str::<SupportedTypes>(
+            format!("\"{}\"", event.table_name.as_str()).as_str(),
+        )
+        .unwrap_or(SupportedTypes::Unknown),
+        false => SupportedTypes::Unknown, // same table name in another source
+    };
 
     ctx.table_id_map.insert(event.table_id, t);
 }
 
-fn fill_fields_map<T: MysqlObject>(
-    db_conf: &config::DB,
-    fields_map: &mut HashMap<String, FieldMapping>,
-    _retry: bool,
-) {
+fn fill_fields_map<T: DbObject>(db_conf: &config::DB, stream: &mut BinlogStream, _retry: bool) {
+    if !source::has_table(&stream.tables, T::table()) {
+        return;
+    }
     // todo: add retry logic
     let fields = match select::get_columns(db_conf, T::table()) {
         Ok(cols) => cols,
@@ -215,5 +349,67 @@ fn fill_fields_map<T: MysqlObject>(
         T::table(),
         type_fields
     );
-    fields_map.insert(T::table().into(), type_fields);
+    stream.fields_map.insert(T::table().into(), type_fields);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::history::{Change, FieldChange};
+    use mysql_cdc::events::row_events::mysql_value::MySqlValue;
+
+    fn package_row(id: u32, name: &str) -> RowData {
+        RowData::new(vec![
+            Some(MySqlValue::Int(id)),
+            Some(MySqlValue::String(name.to_string())),
+        ])
+    }
+
+    #[test]
+    fn test_update_is_a_field_change() {
+        let mapping = FieldMapping::from([(String::from("id"), 0), (String::from("name"), 1)]);
+        let mut stream = BinlogStream {
+            tables: vec![String::from("package")],
+            table_id_map: HashMap::from([(7, SupportedTypes::Package)]),
+            fields_map: HashMap::from([(String::from("package"), mapping)]),
+            slave_cli: BinlogClient::new(build_slave_cli_opts(&config::DB::default(), 1, None)),
+            stat: Arc::new(EngineStat::default()),
+            origin: EventOrigin::default(),
+            position: None,
+            pending_gtid: None,
+            events: Vec::new(),
+        };
+        let (old, new) = (package_row(1, "old"), package_row(1, "new"));
+        push_change(&mut stream, 8, EventType::Update, &new, Some(&old));
+        assert!(stream.events.is_empty());
+        push_change(&mut stream, 7, EventType::Update, &new, Some(&old));
+
+        let (new, old) = match stream.events.pop() {
+            Some(SourceEvent::Change {
+                ev_type: EventType::Update,
+                object: SourceObject::Package(new),
+                old: Some(SourceObject::Package(old)),
+                ..
+            }) => (new, old),
+            event => panic!("unexpected event: {:?}", event),
+        };
+        let change = Change {
+            event: EventType::Update.name(),
+            old: serde_json::to_value(old).ok(),
+            new: serde_json::to_value(new).ok(),
+            gtid: None,
+            binlog_ts: 0,
+            applied_ts: 0,
+            visible_iteration: 1,
+            visible_ts: None,
+        };
+        assert_eq!(
+            vec![FieldChange {
+                field: String::from("name"),
+                old: Some(serde_json::json!("old")),
+                new: Some(serde_json::json!("new")),
+            }],
+            change.field_changes()
+        );
+    }
 }
@@ -0,0 +1,158 @@
+use crate::data::objects::{Campaign, Package, Pad, PadRelation, TargetingPad};
+use crate::data::objects_traits::{DbObject, Storable, StorableRaw};
+use crate::data::source::Positions;
+use crate::data::store::Store;
+use crate::helpers;
+
+use logging_timer::stime;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::time::Instant;
+
+// bump on any change of the layout below or of the stored structs
+pub const SNAPSHOT_VERSION: u32 = 2;
+const MAGIC: &[u8; 4] = b"IXDS";
+
+// file layout: MAGIC, version as u32 LE, bincode header, then one bincode
+// (table, objects) section per table, same order in save and load_pair
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotHeader {
+    pub created_ts: u64,
+    pub iteration: u64,
+    pub positions: Positions, // replication of every source resumes from here
+}
+
+// writes through a temp file, so a crash never leaves a truncated snapshot behind
+#[stime("info")]
+pub fn save(path: &str, store: &Store, positions: Positions) -> Result<(), Box<dyn Error>> {
+    let start = Instant::now();
+    let tmp_path = format!("{}.tmp", path);
+    let file = File::create(&tmp_path)?;
+    let mut writer = BufWriter::new(file);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+    let header = SnapshotHeader {
+        created_ts: helpers::time::cur_ts(),
+        iteration: store.get_store_stat().iteration,
+        positions,
+    };
+    bincode::serialize_into(&mut writer, &header)?;
+
+    write_objects::<Campaign>(&mut writer, store)?;
+    write_objects::<Package>(&mut writer, store)?;
+    write_objects::<Pad>(&mut writer, store)?;
+    write_objects::<PadRelation>(&mut writer, store)?;
+    write_objects::<TargetingPad>(&mut writer, store)?;
+
+    let file = writer.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, path)?;
+    log::info!(
+        "snapshot saved to {}: iteration={}, positions={:?}, took {:?}",
+        path,
+        header.iteration,
+        header.positions,
+        start.elapsed()
+    );
+    Ok(())
+}
+
+// two identical stores, same as select::load_pair but from a snapshot file
+#[stime("info")]
+pub fn load_pair(path: &str) -> Result<(Store, Store, SnapshotHeader), Box<dyn Error>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(format!("{} is not a store snapshot", path).into());
+    }
+    let mut version = [0u8; 4];
+    reader.read_exact(&mut version)?;
+    let version = u32::from_le_bytes(version);
+    if version != SNAPSHOT_VERSION {
+        return Err(format!(
+            "snapshot version {} isn't supported, expected {}",
+            version, SNAPSHOT_VERSION
+        )
+        .into());
+    }
+    let header: SnapshotHeader = bincode::deserialize_from(&mut reader)?;
+
+    let mut stores = [Store::default(), Store::default()];
+    read_objects::<Campaign>(&mut reader, &mut stores)?;
+    read_objects::<Package>(&mut reader, &mut stores)?;
+    read_objects::<Pad>(&mut reader, &mut stores)?;
+    read_objects::<PadRelation>(&mut reader, &mut stores)?;
+    read_objects::<TargetingPad>(&mut reader, &mut stores)?;
+
+    let [first, second] = stores;
+    Ok((first, second, header))
+}
+
+fn write_objects<T>(writer: &mut impl Write, store: &Store) -> Result<(), Box<dyn Error>>
+where
+    T: DbObject + StorableRaw + Serialize + DeserializeOwned + Clone + 'static,
+{
+    let objects = store.get_raw_data().iter::<T>().collect::<Vec<_>>();
+    bincode::serialize_into(writer, &(T::table(), objects))?;
+    Ok(())
+}
+
+fn read_objects<T>(reader: &mut impl Read, stores: &mut [Store]) -> Result<(), Box<dyn Error>>
+where
+    T: DbObject + Storable + DeserializeOwned + Clone,
+{
+    let (table, objects): (String, Vec<T>) = bincode::deserialize_from(reader)?;
+    if table != T::table() {
+        return Err(format!("expected table {} in snapshot, got {}", T::table(), table).into());
+    }
+    for object in objects {
+        for store in stores.iter_mut() {
+            object.clone().insert(store);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::consistency;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_save_load() {
+        let mut store = Store::default();
+        Campaign {
+            id: 1,
+            name: String::from("first"),
+            package_id: 10,
+            ..Default::default()
+        }
+        .insert(&mut store);
+        Package {
+            id: 10,
+            name: String::from("package"),
+        }
+        .insert(&mut store);
+        store.rebuild_index(7);
+
+        let path = std::env::temp_dir().join(format!("indexerd-snapshot-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let positions = Positions::from([(String::from("default"), String::from("uuid:1-5"))]);
+        save(path, &store, positions.clone()).unwrap();
+        let (first, second, header) = load_pair(path).unwrap();
+        let _ = std::fs::remove_file(path);
+
+        assert_eq!((7, positions), (header.iteration, header.positions));
+        for loaded in [first, second] {
+            let diffs =
+                consistency::diff(store.get_raw_data(), loaded.get_raw_data(), &HashSet::new());
+            assert!(diffs.iter().all(|d| d.is_empty()));
+            assert_eq!(2, diffs.iter().map(|d| d.compared).sum::<usize>());
+        }
+    }
+}
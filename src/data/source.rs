@@ -0,0 +1,258 @@
+use crate::config;
+use crate::data::history::EventOrigin;
+use crate::data::objects::{Campaign, IdType, Package, Pad, PadRelation, TargetingPad};
+use crate::data::objects_traits::{Storable, StorableRaw};
+use crate::data::registry;
+use crate::data::store::Store;
+use crate::data::updater::{self, EventType, UpdaterPtr};
+use crate::helpers;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// position of every source by its name; a source without one streams from its start
+pub type Positions = BTreeMap<String, String>;
+
+// where the updater gets objects and their changes from; positions are opaque
+// checkpoints of the source, e.g. a gtid set for mysql
+pub trait ChangeSource: Send + Sync {
+    // kind and address for logs
+    fn name(&self) -> String;
+    // current position, a load started after it reflects at least everything before it
+    fn position(&self) -> Result<Option<String>, Box<dyn Error>>;
+    // tables load_table serves
+    fn tables(&self) -> Vec<&'static str>;
+    // every object of the table in chunks of up to chunk_size, on a connection of its own;
+    // tables are loaded in parallel
+    fn load_table(
+        &self,
+        table: &str,
+        chunk_size: usize,
+        f: &mut dyn FnMut(Vec<SourceObject>),
+    ) -> Result<(), Box<dyn Error>>;
+    // whether changes after position are still kept, a warm start needs them
+    fn can_resume(&self, _position: &str) -> Result<bool, Box<dyn Error>> {
+        Ok(true)
+    }
+    // changes after position, or from the beginning of what the source keeps
+    fn stream(&self, position: Option<String>) -> Result<Box<dyn ChangeStream>, Box<dyn Error>>;
+}
+
+pub trait ChangeStream: Send {
+    // next batch in source order; may block for a while and return nothing,
+    // the slave loop checks the stop flag in between
+    fn next_events(&mut self) -> Result<Vec<SourceEvent>, Box<dyn Error>>;
+}
+
+// source under its configured name, which keys its position and stat
+#[derive(Clone)]
+pub struct NamedSource {
+    pub name: String,
+    pub source: Arc<dyn ChangeSource>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct SourceStatus {
+    pub name: String,
+    pub address: String,
+    pub position: Option<String>, // applied to write_store
+    pub lag_sec: u64,             // since the commit of the last change, 0 when idle
+    pub changes: u64,
+}
+
+// progress of the last load from the sources, by table
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct LoadProgress {
+    pub purpose: String, // initial, full_reload or consistency
+    pub started_ts: u64,
+    pub finished_ts: Option<u64>,
+    pub tables: Vec<TableLoad>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct TableLoad {
+    pub source: String,
+    pub table: String,
+    pub rows: u64, // inserted into the stores so far
+    pub done: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum SourceEvent {
+    Change {
+        ev_type: EventType,
+        object: SourceObject, // deleted object for deletes
+        old: Option<SourceObject>,
+        origin: EventOrigin,
+    },
+    // everything before is applied, the stream resumes after position
+    Commit {
+        position: String,
+    },
+}
+
+macro_rules! source_objects {
+    ($($t:ident),*) => {
+        // object of any supported table
+        #[derive(Debug, Clone)]
+        pub enum SourceObject {
+            $($t($t)),*
+        }
+
+        $(impl From<$t> for SourceObject {
+            fn from(object: $t) -> Self {
+                SourceObject::$t(object)
+            }
+        })*
+
+        impl SourceObject {
+            pub fn id(&self) -> IdType {
+                match self {
+                    $(SourceObject::$t(object) => object.get_id()),*
+                }
+            }
+
+            // old of another table is dropped
+            pub fn apply(
+                self,
+                updater: &UpdaterPtr,
+                old: Option<SourceObject>,
+                ev_type: EventType,
+                origin: Option<EventOrigin>,
+            ) {
+                match self {
+                    $(SourceObject::$t(object) => {
+                        let old = match old {
+                            Some(SourceObject::$t(old)) => Some(old),
+                            _ => None,
+                        };
+                        updater::apply_to_store(updater, object, old, ev_type, origin);
+                    })*
+                }
+            }
+
+            pub fn insert_into(self, stores: &mut [Store]) {
+                match self {
+                    $(SourceObject::$t(object) => {
+                        for store in stores.iter_mut() {
+                            object.clone().insert(store);
+                        }
+                    })*
+                }
+            }
+        }
+    };
+}
+
+source_objects!(Campaign, Package, Pad, PadRelation, TargetingPad);
+
+// a source serves only tables of its set, empty means every table
+pub fn has_table(tables: &[String], table: &str) -> bool {
+    tables.is_empty() || tables.iter().any(|t| t == table)
+}
+
+// supported tables of the set in load order
+pub fn source_tables(tables: &[String]) -> Vec<&'static str> {
+    registry::object_types()
+        .into_iter()
+        .map(|object_type| object_type.table)
+        .filter(|table| has_table(tables, table))
+        .collect()
+}
+
+// stores straight from the sources, bypassing the updater; tables are fetched by
+// conf.threads loaders while this thread is the only one inserting into the stores
+pub fn load_stores<const N: usize>(
+    sources: &[NamedSource],
+    conf: &config::Load,
+    progress: &Mutex<LoadProgress>,
+    purpose: &str,
+) -> Result<[Store; N], String> {
+    let tasks = sources
+        .iter()
+        .flat_map(|named| named.source.tables().into_iter().map(move |t| (named, t)))
+        .collect::<Vec<_>>();
+    *progress.lock().unwrap() = LoadProgress {
+        purpose: purpose.to_string(),
+        started_ts: helpers::time::cur_ts(),
+        finished_ts: None,
+        tables: tasks
+            .iter()
+            .map(|(named, table)| TableLoad {
+                source: named.name.clone(),
+                table: table.to_string(),
+                ..Default::default()
+            })
+            .collect(),
+    };
+
+    let threads = conf.threads.clamp(1, tasks.len().max(1));
+    let chunk_size = conf.chunk_size.max(1);
+    let (task_snd, task_rcv) = crossbeam_channel::unbounded();
+    for task in tasks.into_iter().enumerate() {
+        let _ = task_snd.send(task);
+    }
+    drop(task_snd);
+    // bounded, so fetching never runs far ahead of inserting
+    let (chunk_snd, chunk_rcv) =
+        crossbeam_channel::bounded::<(usize, Vec<SourceObject>)>(threads * 2);
+    let failed = AtomicBool::new(false);
+
+    let mut stores: [Store; N] = std::array::from_fn(|_| Store::default());
+    thread::scope(|scope| {
+        for n in 0..threads {
+            let (task_rcv, chunk_snd, failed) = (task_rcv.clone(), chunk_snd.clone(), &failed);
+            thread::Builder::new()
+                .name(format!("load-{}", n))
+                .spawn_scoped(scope, move || {
+                    for (num, (named, table)) in task_rcv.iter() {
+                        // tables already started are finished, new ones aren't
+                        if failed.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        let result = named
+                            .source
+                            .load_table(table, chunk_size, &mut |chunk| {
+                                let _ = chunk_snd.send((num, chunk));
+                            })
+                            .map_err(|e| e.to_string());
+                        let mut progress = progress.lock().unwrap();
+                        match result {
+                            Ok(()) => progress.tables[num].done = true,
+                            Err(e) => {
+                                log::error!("fail to load {} from {}: {}", table, named.name, e);
+                                progress.tables[num].error = Some(e);
+                                failed.store(true, Ordering::Relaxed);
+                            }
+                        }
+                    }
+                })
+                .expect("fail to run load thread");
+        }
+        drop(chunk_snd);
+
+        for (num, chunk) in chunk_rcv.iter() {
+            let rows = chunk.len() as u64;
+            for object in chunk {
+                object.insert_into(&mut stores);
+            }
+            progress.lock().unwrap().tables[num].rows += rows;
+        }
+    });
+
+    let mut progress = progress.lock().unwrap();
+    progress.finished_ts = Some(helpers::time::cur_ts());
+    match progress.tables.iter().find(|t| t.error.is_some()) {
+        Some(failed) => Err(format!(
+            "fail to load {} from source {}: {}",
+            failed.table,
+            failed.source,
+            failed.error.as_deref().unwrap_or_default()
+        )),
+        None => Ok(stores),
+    }
+}
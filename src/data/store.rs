@@ -1,10 +1,15 @@
 use crate::data::aci::ActiveCampaignIndex;
+use crate::data::mapped::MappedTables;
+use crate::data::memory::StoreMemory;
 use crate::data::objects::{Campaign, Package, Pad};
 use crate::data::objects::{PadRelation, TargetingPad};
 use crate::data::objects_traits::Storable;
 use crate::data::raw_storage;
+use crate::data::registry;
 use crate::helpers;
 use serde::{Deserialize, Serialize};
+use std::mem::size_of;
+use std::sync::Arc;
 
 #[derive(Default, Clone, Deserialize, Serialize)]
 pub struct IndexStat {
@@ -12,6 +17,7 @@ pub struct IndexStat {
     pub rebuild_start_ts: u64,
     pub rebuild_end_ts: u64,
     pub rebuild_duration_sec: u64,
+    pub memory: StoreMemory, // measured when the store is published
 }
 #[derive(Default)]
 pub struct Store {
@@ -31,12 +37,28 @@ impl Store {
         self.index_stat.rebuild_duration_sec =
             self.index_stat.rebuild_end_ts - self.index_stat.rebuild_start_ts;
     }
+    // walks all objects, call on publish or from cron only
+    pub fn memory(&self) -> StoreMemory {
+        let tables = registry::object_types()
+            .iter()
+            .map(|t| (t.memory)(&self.raw_data))
+            .collect();
+        let index_bytes = size_of::<ActiveCampaignIndex>() + self._aci.name.capacity();
+        StoreMemory::new(tables, index_bytes)
+    }
+    pub fn measure_memory(&mut self) {
+        self.index_stat.memory = self.memory();
+    }
     pub fn get_store_stat(&self) -> &IndexStat {
         &self.index_stat
     }
     pub fn get_raw_data(&self) -> &raw_storage::Storage {
         &self.raw_data
     }
+    // drops heap objects, mapped must hold the same data
+    pub fn set_mapped(&mut self, mapped: Arc<MappedTables>) {
+        self.raw_data = raw_storage::Storage::with_base(mapped);
+    }
 }
 
 impl Storable for Campaign {
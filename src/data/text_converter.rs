@@ -0,0 +1,44 @@
+use crate::data::slave::FieldMapping;
+
+// values in postgres text format, used for pgoutput rows and the initial select
+pub trait FromText: Sized {
+    fn from_text(value: &str) -> Option<Self>;
+}
+
+// missing column or null gives the default, same as an unparsable value with a warning
+pub fn convert_text<T: FromText + Default>(
+    values: &[Option<String>],
+    mapping: &FieldMapping,
+    field: &str,
+) -> T {
+    let value = match mapping.get(field).and_then(|pos| values.get(*pos)) {
+        Some(Some(value)) => value,
+        _ => return T::default(),
+    };
+    T::from_text(value).unwrap_or_else(|| {
+        log::warn!("fail to convert '{}' of field {}", value, field);
+        T::default()
+    })
+}
+
+macro_rules! from_str_text {
+    ($($t:ty),*) => {
+        $(impl FromText for $t {
+            fn from_text(value: &str) -> Option<Self> {
+                value.parse().ok()
+            }
+        })*
+    };
+}
+
+from_str_text!(i32, i64, u32, u64, String);
+
+impl FromText for bool {
+    fn from_text(value: &str) -> Option<Self> {
+        match value {
+            "t" | "true" | "1" => Some(true),
+            "f" | "false" | "0" => Some(false),
+            _ => None,
+        }
+    }
+}
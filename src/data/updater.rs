@@ -1,77 +1,225 @@
 use crate::config;
-use crate::data::objects_traits::Storable;
+use crate::data::consistency::{self, ConsistencyReport, ObjectKey};
+use crate::data::file_source::FileSource;
+use crate::data::history::{Change, EventOrigin};
+use crate::data::mapped::MappedTables;
+use crate::data::memory::StoreMemory;
+use crate::data::objects_traits::{DbObject, Storable, StorableRaw};
+use crate::data::pg_source::PgSource;
+use crate::data::slave::MysqlSource;
+use crate::data::source::{
+    self, ChangeSource, ChangeStream, LoadProgress, NamedSource, Positions, SourceEvent,
+    SourceStatus,
+};
 use crate::data::store::Store;
-use crate::data::{select, slave};
+use crate::data::{registry, snapshot};
 use crate::engine;
 use crate::helpers;
+use crate::metrics::{Histogram, MetricsWriter, SWAP_BUCKETS};
 
 use logging_timer::stime;
-use mysql_cdc::providers::mysql::gtid::gtid_set::GtidSet;
+use serde::Serialize;
+use std::collections::HashSet;
 use std::error::Error;
 use std::fmt::Debug;
-use std::ops::{AddAssign, DerefMut};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::mem;
+use std::ops::AddAssign;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use std::sync::RwLock;
+use std::sync::{Mutex, RwLock};
 use std::thread::{sleep, JoinHandle};
+use std::time::Instant;
 use std::{thread, time};
 
 pub type UpdaterPtr = Arc<RwLock<Updater>>;
 pub type SlaveUpdateFunc = Box<dyn FnOnce(&mut Store) + Send + Sync>;
 
+const MAPPED_PREFIX: &str = "index-";
+
 pub struct Updater {
     pub conf: config::Updater,
     pub stop_flag: Arc<AtomicBool>,
     engine: Arc<RwLock<engine::Engine>>,
-    read_store: Arc<RwLock<Store>>,
-    write_store: Arc<RwLock<Store>>,
-    slave: Option<JoinHandle<()>>,
+    sources: Vec<NamedSource>,
+    // mutable builder; None until workers release the snapshot it was published as
+    write_store: Option<Store>,
+    retired_store: Option<Arc<Store>>,
+    slaves: Vec<JoinHandle<()>>, // one per source
     cron: Option<JoinHandle<()>>,
     index_iteration: u64,
     slave_updates: Vec<SlaveUpdateFunc>,
+    slave_updates_keys: HashSet<ObjectKey>, // objects touched by slave_updates
+    positions: Positions,                   // source checkpoints fully applied to write_store
+    published_positions: Option<Positions>, // of the published store, for snapshots
+    write_store_backlog: Vec<SlaveUpdateFunc>, // events waiting for write_store to come back
+    // events applied while a full reload loads, for both of the loaded stores
+    reload_backlog: Option<Vec<(SlaveUpdateFunc, SlaveUpdateFunc)>>,
+    touched_keys: Option<HashSet<ObjectKey>>, // applied while a consistency check loads
+    engine_stat: Arc<engine::EngineStat>,
+}
+
+pub const BINLOG_EVENT_TYPES: [&str; 5] = [
+    "write_rows",
+    "update_rows",
+    "delete_rows",
+    "table_map",
+    "other",
+];
+
+pub struct UpdaterStat {
+    pub store_swaps: AtomicU64,
+    pub store_swap_duration: Histogram, // rebuild + publish, without waiting for workers
+    pub slave_updates: AtomicU64,
+    pub write_store_backlog: AtomicU64,
+    pub replication_lag_sec: AtomicU64,
+    pub binlog_events: [AtomicU64; BINLOG_EVENT_TYPES.len()],
+    pub write_store_memory: Mutex<StoreMemory>, // measured when write_store comes back
+    pub memory_limit_bytes: AtomicU64,
+    pub memory_limit_exceeded: AtomicBool,
+    pub sources: Mutex<Vec<SourceStatus>>, // same order as Updater::sources
+    pub load: Mutex<LoadProgress>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SwapReport {
+    pub iteration: u64,
+    pub duration_ms: u64,
+    pub released: bool, // workers let go of the old snapshot within store_switch_timeout_ms
 }
 
 #[derive(Debug, Clone)]
 pub enum EventType {
     Insert,
-    #[allow(dead_code)]
     Update,
-    #[allow(dead_code)]
     Delete,
 }
 
+impl EventType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventType::Insert => "insert",
+            EventType::Update => "update",
+            EventType::Delete => "delete",
+        }
+    }
+}
+
 impl Updater {
     pub fn new(
         conf: &config::Updater,
+        cores: &config::CoreAssignment,
         engine: Arc<RwLock<engine::Engine>>,
+    ) -> Result<UpdaterPtr, Box<dyn Error>> {
+        let engine_stat = engine.read().unwrap().stat();
+        let mut sources = Vec::new();
+        for named in conf.named_sources()? {
+            if let Some(table) = named.tables.iter().find(|t| registry::find(t).is_none()) {
+                return Err(format!("unknown table {} in source {}", table, named.name).into());
+            }
+            let tables = &named.tables;
+            let source: Arc<dyn ChangeSource> = match &named.source {
+                config::Source::Mysql => {
+                    let stat = engine_stat.clone();
+                    Arc::new(MysqlSource::new(&named.db, named.server_id, tables, stat))
+                }
+                config::Source::File(file_conf) => Arc::new(FileSource::new(file_conf, tables)),
+                config::Source::Postgres(pg_conf) => Arc::new(PgSource::new(pg_conf, tables)),
+            };
+            sources.push(NamedSource {
+                name: named.name,
+                source,
+            });
+        }
+        Self::with_sources(conf, cores, engine, sources)
+    }
+
+    pub fn with_sources(
+        conf: &config::Updater,
+        cores: &config::CoreAssignment,
+        engine: Arc<RwLock<engine::Engine>>,
+        sources: Vec<NamedSource>,
     ) -> Result<UpdaterPtr, Box<dyn Error>> {
         let stop_flag = Arc::new(AtomicBool::new(false));
+        let engine_stat = engine.read().unwrap().stat();
+        engine_stat.history.configure(&conf.history);
+        if let Some(dir) = conf.mapped_dir.as_ref() {
+            remove_stale_mapped(dir);
+        }
 
-        let mut store_first = Store::default();
+        // either way both stores are filled and one is served right away,
+        // slaves catch up from the positions
+        let snapshot = conf
+            .snapshot
+            .as_ref()
+            .and_then(|snapshot_conf| load_snapshot(snapshot_conf, &sources));
+        let (mut store_first, mut store_second, iteration, positions) = match snapshot {
+            Some(loaded) => loaded,
+            None => {
+                // taken before the load, which then reflects at least everything before them
+                let positions = current_positions(&sources)?;
+                let [first, second] = source::load_stores(
+                    &sources,
+                    &conf.load,
+                    &engine_stat.updater.load,
+                    "initial",
+                )?;
+                (first, second, 1, positions)
+            }
+        };
+        *engine_stat.updater.sources.lock().unwrap() = sources
+            .iter()
+            .map(|named| SourceStatus {
+                name: named.name.clone(),
+                address: named.source.name(),
+                position: positions.get(&named.name).cloned(),
+                ..Default::default()
+            })
+            .collect();
+        for named in sources.iter() {
+            log::info!(
+                "source {} ({}) starts at position {:?}",
+                named.name,
+                named.source.name(),
+                positions.get(&named.name)
+            );
+        }
         store_first.id = String::from("first");
-        let mut store_second = Store::default();
         store_second.id = String::from("second");
+        store_second.rebuild_index(iteration);
+        store_second.measure_memory();
+        engine
+            .write()
+            .unwrap()
+            .set_new_store(Arc::new(store_second));
         let updater_ptr = Arc::new(RwLock::new(Updater {
             conf: conf.clone(),
             stop_flag: stop_flag.clone(),
             engine,
-            read_store: Arc::new(RwLock::new(store_first)),
-            write_store: Arc::new(RwLock::new(store_second)),
-            slave: None,
+            sources: sources.clone(),
+            write_store: Some(store_first),
+            retired_store: None,
+            slaves: Vec::new(),
             cron: None,
-            index_iteration: 0,
+            index_iteration: iteration,
             slave_updates: Vec::new(),
+            slave_updates_keys: HashSet::new(),
+            write_store_backlog: Vec::new(),
+            reload_backlog: None,
+            touched_keys: None,
+            engine_stat,
+            published_positions: Some(positions.clone()),
+            positions,
         }));
 
-        let last_gtid = select::get_master_gtid(&conf.db)?;
-        log::info!("Got last gtid: {:?}", last_gtid);
-
-        select::init(&updater_ptr, &conf.db)?;
-
-        let slave = run_slave(updater_ptr.clone(), last_gtid);
-        let cron = run_cron(updater_ptr.clone());
+        let slaves = sources
+            .into_iter()
+            .enumerate()
+            .map(|(num, named)| run_slave(updater_ptr.clone(), cores.slave, num, named))
+            .collect();
+        let cron = run_cron(updater_ptr.clone(), cores.cron);
         if let Ok(mut updater) = updater_ptr.write() {
-            updater.slave = Some(slave);
+            updater.slaves = slaves;
             updater.cron = Some(cron);
         } else {
             return Err("fail to assign threads".into());
@@ -79,64 +227,650 @@ impl Updater {
         Ok(updater_ptr)
     }
 
-    #[stime("info")]
-    pub fn stop(&mut self) {
-        self.stop_flag.store(true, Ordering::Relaxed);
-        let _ = self.slave.take().unwrap().join();
-        let _ = self.cron.take().unwrap().join();
+    // published store and the positions it is built up to; None until there's a consistent
+    // pair or while a source has no position to resume from
+    fn snapshot_job(&self) -> Option<SnapshotJob> {
+        let conf = self.conf.snapshot.as_ref()?;
+        let positions = self.published_positions.clone()?;
+        if positions.len() != self.sources.len() {
+            return None;
+        }
+        Some(SnapshotJob {
+            path: conf.path.clone(),
+            positions,
+            store: self.engine.read().unwrap().store(),
+        })
+    }
+
+    pub fn engine_stat(&self) -> Arc<engine::EngineStat> {
+        self.engine_stat.clone()
+    }
+
+    // checks the published store and write_store against memory_limit_mb
+    fn account_memory(&self, write_memory: StoreMemory) {
+        let stat = &self.engine_stat.updater;
+        let published = self.engine.read().unwrap().store();
+        let used = (published.get_store_stat().memory.heap_bytes + write_memory.heap_bytes) as u64;
+        let limit = self.conf.memory_limit_mb * 1024 * 1024;
+        let exceeded = limit > 0 && used > limit;
+        let was_exceeded = stat.memory_limit_exceeded.swap(exceeded, Ordering::Relaxed);
+        if exceeded {
+            log::error!(
+                "MEMORY LIMIT EXCEEDED: stores use ~{} MB of {} MB allowed, reporting not ready",
+                used / 1024 / 1024,
+                self.conf.memory_limit_mb
+            );
+        } else if was_exceeded {
+            log::warn!(
+                "memory is back under the limit: ~{} MB of {} MB",
+                used / 1024 / 1024,
+                self.conf.memory_limit_mb
+            );
+        }
+        stat.memory_limit_bytes.store(limit, Ordering::Relaxed);
+        *stat.write_store_memory.lock().unwrap() = write_memory;
+    }
+
+    fn update_backlog_stat(&self) {
+        let stat = &self.engine_stat.updater;
+        stat.slave_updates
+            .store(self.slave_updates.len() as u64, Ordering::Relaxed);
+        stat.write_store_backlog
+            .store(self.write_store_backlog.len() as u64, Ordering::Relaxed);
     }
 }
 
+// joins the threads without holding the updater, they may need it to finish what they do
 #[stime("info")]
-fn swap_stores(updater: &UpdaterPtr) {
+pub fn stop(updater: &UpdaterPtr) {
+    let threads = {
+        let mut updater_w = updater.write().unwrap();
+        updater_w.stop_flag.store(true, Ordering::Relaxed);
+        let mut threads = updater_w.slaves.drain(..).collect::<Vec<_>>();
+        threads.extend(updater_w.cron.take());
+        threads
+    };
+    for thread in threads {
+        let _ = thread.join();
+    }
+    let job = updater.read().unwrap().snapshot_job();
+    if let Some(job) = job {
+        job.run();
+    }
+}
+
+// written without the updater lock; holding the store only delays taking it back as write_store
+struct SnapshotJob {
+    path: String,
+    positions: Positions,
+    store: Arc<Store>,
+}
+
+impl SnapshotJob {
+    fn run(self) {
+        if let Err(e) = snapshot::save(&self.path, &self.store, self.positions) {
+            log::error!("fail to save snapshot to {}: {}", self.path, e);
+        }
+    }
+}
+
+fn load_snapshot(
+    conf: &config::Snapshot,
+    sources: &[NamedSource],
+) -> Option<(Store, Store, u64, Positions)> {
+    let (first, second, header) = match snapshot::load_pair(&conf.path) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            log::warn!("no warm start from {}: {}", conf.path, e);
+            return None;
+        }
+    };
+    if let Some(named) = sources
+        .iter()
+        .find(|named| !header.positions.contains_key(&named.name))
+    {
+        log::warn!(
+            "no warm start, snapshot {} has no position of source {}",
+            conf.path,
+            named.name
+        );
+        return None;
+    }
+    for named in sources.iter() {
+        let position = &header.positions[&named.name];
+        match named.source.can_resume(position) {
+            Ok(true) => {}
+            Ok(false) => {
+                log::warn!(
+                    "no warm start, source {} can't resume from {} of snapshot {}",
+                    named.name,
+                    position,
+                    conf.path
+                );
+                return None;
+            }
+            Err(e) => {
+                log::warn!("no warm start, fail to check source {}: {}", named.name, e);
+                return None;
+            }
+        }
+    }
+    log::info!(
+        "warm start from {}: iteration={}, created_ts={}, positions={:?}",
+        conf.path,
+        header.iteration,
+        header.created_ts,
+        header.positions
+    );
+    Some((first, second, header.iteration, header.positions))
+}
+
+// taken before the initial load, so replication starts no later than the loaded data
+fn current_positions(sources: &[NamedSource]) -> Result<Positions, Box<dyn Error>> {
+    let mut positions = Positions::new();
+    for named in sources.iter() {
+        if let Some(position) = named.source.position()? {
+            positions.insert(named.name.clone(), position);
+        }
+    }
+    Ok(positions)
+}
+
+// called by the slave once all events of the transaction are applied
+pub fn set_position(updater: &UpdaterPtr, num: usize, position: String) {
     let mut updater_w = updater.write().unwrap();
+    let name = updater_w.sources[num].name.clone();
+    if let Some(status) = updater_w
+        .engine_stat
+        .updater
+        .sources
+        .lock()
+        .unwrap()
+        .get_mut(num)
     {
+        status.position = Some(position.clone());
+    }
+    updater_w.positions.insert(name, position);
+}
+
+#[stime("info")]
+pub fn swap_stores(updater: &UpdaterPtr) -> Result<SwapReport, String> {
+    let swap_start = Instant::now();
+    // events go to write_store_backlog while the store is out being built
+    let (mut store, iteration, positions, mapped_dir) = {
+        let mut updater_w = updater.write().unwrap();
+        let store = match updater_w.write_store.take() {
+            Some(store) => store,
+            None => {
+                log::warn!("swap_stores: previous snapshot isn't released yet, skip");
+                return Err("previous snapshot isn't released yet".to_string());
+            }
+        };
         updater_w.index_iteration.add_assign(1);
-        let mut write_store_w = updater_w.write_store.write().unwrap();
-        write_store_w.rebuild_index(updater_w.index_iteration);
+        updater_w.slave_updates_keys.clear();
+        (
+            store,
+            updater_w.index_iteration,
+            updater_w.positions.clone(),
+            updater_w.conf.mapped_dir.clone(),
+        )
+    };
+    build_published(&mut store, iteration, mapped_dir.as_deref());
+
+    let (retired, engine_stat, timeout) = {
+        let mut updater_w = updater.write().unwrap();
+        // a full reload while building replaced both stores, this one is stale
+        if updater_w.index_iteration != iteration {
+            return Err("stores were replaced by a full reload".to_string());
+        }
+        let retired = updater_w
+            .engine
+            .write()
+            .unwrap()
+            .set_new_store(Arc::new(store));
+        updater_w.published_positions = Some(positions);
+        updater_w
+            .engine_stat
+            .history
+            .mark_published(iteration, helpers::time::cur_ts());
+
+        // retired snapshot becomes the next write_store, it misses everything since last
+        // swap; the published one misses what came while it was built
+        let during_build = mem::take(&mut updater_w.write_store_backlog);
+        updater_w.write_store_backlog = mem::replace(&mut updater_w.slave_updates, during_build);
+        updater_w.update_backlog_stat();
+        (
+            retired,
+            updater_w.engine_stat.clone(),
+            time::Duration::from_millis(updater_w.conf.store_switch_timeout_ms),
+        )
+    };
+
+    engine_stat
+        .updater
+        .store_swaps
+        .fetch_add(1, Ordering::Relaxed);
+    engine_stat
+        .updater
+        .store_swap_duration
+        .observe(swap_start.elapsed());
+
+    // wait without holding the updater: slave keeps queueing events meanwhile
+    let released = engine_stat.wait_store_release(&retired, timeout);
+    if let Err(lagging) = released.as_ref() {
+        log::warn!(
+            "swap_stores: workers {:?} still use store.id={} after {:?}, postpone write_store updates",
+            lagging,
+            retired.id,
+            timeout
+        );
     }
+    {
+        let mut updater_w = updater.write().unwrap();
+        // a full reload while waiting replaced both stores, the retired one is stale
+        if updater_w.index_iteration == iteration {
+            updater_w.retired_store = Some(retired);
+        }
+    }
+    finish_store_switch(updater);
+    Ok(SwapReport {
+        iteration,
+        duration_ms: swap_start.elapsed().as_millis() as u64,
+        released: released.is_ok(),
+    })
+}
+
+// replaces both stores with a fresh load; slaves aren't blocked by it, what they
+// apply meanwhile is replayed onto both loaded stores, which are at or past it anyway
+#[stime("info")]
+pub fn full_reload(updater: &UpdaterPtr) -> Result<SwapReport, String> {
+    let reload_start = Instant::now();
+    let (sources, load_conf, engine_stat) = {
+        let mut updater_w = updater.write().unwrap();
+        if updater_w.reload_backlog.is_some() {
+            return Err("full reload is already running".to_string());
+        }
+        updater_w.reload_backlog = Some(Vec::new());
+        (
+            updater_w.sources.clone(),
+            updater_w.conf.load.clone(),
+            updater_w.engine_stat.clone(),
+        )
+    };
+    // taken before the load, which then reflects at least everything before them
+    let loaded = current_positions(&sources)
+        .map_err(|e| format!("fail to get source positions: {}", e))
+        .and_then(|positions| {
+            let stores = source::load_stores(
+                &sources,
+                &load_conf,
+                &engine_stat.updater.load,
+                "full_reload",
+            )?;
+            Ok((stores, positions))
+        });
 
-    let tmp = updater_w.write_store.clone();
-    updater_w.write_store = updater_w.read_store.clone();
-    updater_w.read_store = tmp;
+    // both stores are caught up here; while the published one is built, events queue
+    // for write_store and stay in slave_updates for the published one, as in a swap
+    let (mut published, mut write_store, iteration, positions, mapped_dir) = {
+        let mut updater_w = updater.write().unwrap();
+        let reload_backlog = updater_w.reload_backlog.take().unwrap_or_default();
+        let ([mut published, mut write_store], positions) = loaded?;
+        log::info!(
+            "full reload: replay {} events applied during the load",
+            reload_backlog.len()
+        );
+        for (to_published, to_write_store) in reload_backlog {
+            to_published(&mut published);
+            to_write_store(&mut write_store);
+        }
+        updater_w.index_iteration.add_assign(1);
+        // streams reopen from where the load started, replaying is harmless
+        updater_w.positions.extend(positions.clone());
+        // a swap still waiting for its retired store finds it stale by the iteration
+        updater_w.write_store = None;
+        updater_w.retired_store = None;
+        updater_w.slave_updates.clear();
+        updater_w.slave_updates_keys.clear();
+        updater_w.write_store_backlog.clear();
+        updater_w.update_backlog_stat();
+        (
+            published,
+            write_store,
+            updater_w.index_iteration,
+            positions,
+            updater_w.conf.mapped_dir.clone(),
+        )
+    };
+    published.id = String::from("first");
+    write_store.id = String::from("second");
+    build_published(&mut published, iteration, mapped_dir.as_deref());
+
+    let mut updater_w = updater.write().unwrap();
+    if updater_w.index_iteration != iteration {
+        return Err("stores were replaced by another full reload".to_string());
+    }
+    for update_func in mem::take(&mut updater_w.write_store_backlog) {
+        update_func(&mut write_store);
+    }
+    // old snapshot is dropped by whoever releases it last
     updater_w
         .engine
         .write()
         .unwrap()
-        .set_new_store(&updater_w.read_store);
+        .set_new_store(Arc::new(published));
+    updater_w.published_positions = Some(positions);
+    updater_w
+        .engine_stat
+        .history
+        .mark_published(iteration, helpers::time::cur_ts());
 
-    let slave_updates = updater_w.slave_updates.drain(..).collect::<Vec<_>>();
-    // we can't access write_store until all workers switch to the new one
-    {
-        let mut write_store_w = updater_w.write_store.write().unwrap();
-        log::debug!("apply {} events from slave_updates...", slave_updates.len());
-        for update_func in slave_updates.into_iter() {
-            update_func(write_store_w.deref_mut());
+    updater_w.account_memory(write_store.memory());
+    updater_w.write_store = Some(write_store);
+    updater_w.update_backlog_stat();
+
+    let stat = &updater_w.engine_stat.updater;
+    stat.store_swaps.fetch_add(1, Ordering::Relaxed);
+    stat.store_swap_duration.observe(reload_start.elapsed());
+    log::info!("full reload is done. iteration={}", iteration);
+    Ok(SwapReport {
+        iteration,
+        duration_ms: reload_start.elapsed().as_millis() as u64,
+        released: true,
+    })
+}
+
+// diffs the published snapshot against write_store, and write_store against a fresh
+// select if with_db; slaves aren't blocked by the select or the diffs, what they touch
+// during the select is skipped in its diff
+#[stime("info")]
+pub fn check_consistency(updater: &UpdaterPtr, with_db: bool) -> Result<ConsistencyReport, String> {
+    let check_start = Instant::now();
+    let db_load = match with_db {
+        true => Some(load_for_check(updater)?),
+        false => None,
+    };
+    // write_store is out for the diff, events meanwhile queue for it as during a swap
+    let (write_store, published, store_keys, touched_keys, iteration) = {
+        let mut updater_w = updater.write().unwrap();
+        let touched_keys = updater_w.touched_keys.take().unwrap_or_default();
+        let write_store = updater_w
+            .write_store
+            .take()
+            .ok_or("store switch is in progress, try again later")?;
+        let published = updater_w.engine.read().unwrap().store();
+        (
+            write_store,
+            published,
+            updater_w.slave_updates_keys.clone(),
+            touched_keys,
+            updater_w.index_iteration,
+        )
+    };
+
+    // objects changed since the last swap are only in write_store yet
+    let stores = consistency::diff(
+        published.get_raw_data(),
+        write_store.get_raw_data(),
+        &store_keys,
+    );
+    // db may still be ahead by the replication lag, recheck reported ids before acting
+    let (db, db_positions) = match db_load {
+        Some((db_store, positions)) => (
+            Some(consistency::diff(
+                db_store.get_raw_data(),
+                write_store.get_raw_data(),
+                &touched_keys,
+            )),
+            Some(positions),
+        ),
+        None => (None, None),
+    };
+    return_write_store(updater, write_store, iteration);
+
+    let consistent = stores
+        .iter()
+        .chain(db.iter().flatten())
+        .all(|d| d.is_empty());
+    if !consistent {
+        log::warn!(
+            "check_consistency: stores diverged, stores={:?}, db={:?}",
+            stores,
+            db
+        );
+    }
+    Ok(ConsistencyReport {
+        iteration: published.get_store_stat().iteration,
+        duration_ms: check_start.elapsed().as_millis() as u64,
+        consistent,
+        stores,
+        db,
+        db_positions,
+    })
+}
+
+// catches up with what queued meanwhile, unless a full reload replaced both stores
+fn return_write_store(updater: &UpdaterPtr, mut store: Store, iteration: u64) {
+    let mut updater_w = updater.write().unwrap();
+    if updater_w.index_iteration != iteration || updater_w.write_store.is_some() {
+        log::warn!("stores were replaced meanwhile, drop store.id={}", store.id);
+        return;
+    }
+    for update_func in mem::take(&mut updater_w.write_store_backlog) {
+        update_func(&mut store);
+    }
+    updater_w.write_store = Some(store);
+    updater_w.update_backlog_stat();
+}
+
+// keys are tracked before the positions are taken, so anything the select may have
+// and write_store didn't when tracking started gets skipped
+fn load_for_check(updater: &UpdaterPtr) -> Result<(Store, Positions), String> {
+    let (sources, load_conf, engine_stat) = {
+        let mut updater_w = updater.write().unwrap();
+        if updater_w.touched_keys.is_some() {
+            return Err("consistency check is already running".to_string());
+        }
+        updater_w.touched_keys = Some(HashSet::new());
+        (
+            updater_w.sources.clone(),
+            updater_w.conf.load.clone(),
+            updater_w.engine_stat.clone(),
+        )
+    };
+    let loaded = current_positions(&sources)
+        .map_err(|e| format!("fail to get source positions: {}", e))
+        .and_then(|positions| {
+            let [db_store] = source::load_stores(
+                &sources,
+                &load_conf,
+                &engine_stat.updater.load,
+                "consistency",
+            )?;
+            Ok((db_store, positions))
+        });
+    if loaded.is_err() {
+        updater.write().unwrap().touched_keys = None;
+    }
+    loaded
+}
+
+// index and mapped file of a store to be published, built without the updater lock
+fn build_published(store: &mut Store, iteration: u64, mapped_dir: Option<&str>) {
+    store.rebuild_index(iteration);
+    if let Some(dir) = mapped_dir {
+        map_store(store, dir, iteration);
+    }
+    store.measure_memory();
+}
+
+// moves store objects to a mapped file, the heap keeps only changes made after;
+// the file is removed when the last store using it is dropped
+fn map_store(store: &mut Store, dir: &str, iteration: u64) {
+    let path = Path::new(dir).join(format!("{}{}.bin", MAPPED_PREFIX, iteration));
+    match MappedTables::build(&path, store.get_raw_data()) {
+        Ok(mapped) => {
+            log::info!(
+                "store.id={} mapped to {}, {} bytes",
+                store.id,
+                path.display(),
+                mapped.size()
+            );
+            store.set_mapped(Arc::new(mapped));
+        }
+        Err(e) => {
+            log::error!(
+                "fail to map store to {}, keep it on heap: {}",
+                path.display(),
+                e
+            );
+            let _ = std::fs::remove_file(&path);
         }
     }
+}
+
+// files left by a previous run of this instance
+fn remove_stale_mapped(dir: &str) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::warn!("fail to read mapped_dir {}: {}", dir, e);
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with(MAPPED_PREFIX) && name.ends_with(".bin") {
+            log::info!("removing stale mapped index {}", name);
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+}
+
+// takes the retired snapshot back as write_store; no-op while workers still reference it
+fn finish_store_switch(updater: &UpdaterPtr) {
+    let mut updater_w = updater.write().unwrap();
+    let retired = match updater_w.retired_store.take() {
+        Some(retired) => retired,
+        None => return,
+    };
+    let mut store = match Arc::try_unwrap(retired) {
+        Ok(store) => store,
+        Err(retired) => {
+            updater_w.retired_store = Some(retired);
+            return;
+        }
+    };
+    if updater_w.write_store.is_some() {
+        log::warn!(
+            "finish_store_switch: write_store is already set, drop store.id={}",
+            store.id
+        );
+        return;
+    }
+
+    let backlog = updater_w.write_store_backlog.drain(..).collect::<Vec<_>>();
+    log::debug!("apply {} events from slave_updates...", backlog.len());
+    for update_func in backlog.into_iter() {
+        update_func(&mut store);
+    }
 
     log::info!(
-        "swap is done. read_store.id={}, write_store.id={}",
-        updater_w.read_store.read().unwrap().id,
-        updater_w.write_store.read().unwrap().id
+        "swap is done. iteration={}, write_store.id={}",
+        updater_w.index_iteration,
+        store.id
     );
+    updater_w.account_memory(store.memory());
+    updater_w.write_store = Some(store);
+    updater_w.update_backlog_stat();
 }
 
-fn run_slave(updater: UpdaterPtr, gtid: Option<GtidSet>) -> JoinHandle<()> {
+fn run_slave(updater: UpdaterPtr, core: usize, num: usize, named: NamedSource) -> JoinHandle<()> {
     thread::Builder::new()
-        .name(String::from("slave"))
+        .name(format!("slave-{}", named.name))
         .spawn(move || {
-            helpers::bind_thread(1);
-            slave::slave_loop(updater, gtid);
+            helpers::bind_thread(core);
+            slave_loop(updater, num, named);
         })
         .expect("fail to run slave thread")
 }
 
-fn run_cron(updater: UpdaterPtr) -> JoinHandle<()> {
+// applies changes of one source to write_store until stop
+fn slave_loop(updater: UpdaterPtr, num: usize, named: NamedSource) {
+    let (stop_flag, engine_stat) = {
+        let updater_r = updater.read().unwrap();
+        (updater_r.stop_flag.clone(), updater_r.engine_stat.clone())
+    };
+    let mut stop_checker = helpers::StopChecker::new(stop_flag);
+    let mut stream: Option<Box<dyn ChangeStream>> = None;
+
+    while !stop_checker.is_time() {
+        let cur_stream = match stream.as_mut() {
+            Some(stream) => stream,
+            None => {
+                // retries resume from what is applied already
+                let position = updater.read().unwrap().positions.get(&named.name).cloned();
+                match named.source.stream(position.clone()) {
+                    Ok(opened) => stream.insert(opened),
+                    Err(e) => {
+                        log::error!("fail to open source {} stream: {}", named.name, e);
+                        // changes after position are gone, only a fresh load catches up
+                        let lost = position
+                            .is_some_and(|p| matches!(named.source.can_resume(&p), Ok(false)));
+                        match lost {
+                            true => match full_reload(&updater) {
+                                Ok(report) => log::info!(
+                                    "source {} resynced by a full reload, iteration={}",
+                                    named.name,
+                                    report.iteration
+                                ),
+                                Err(e) => {
+                                    log::error!("fail to resync source {}: {}", named.name, e)
+                                }
+                            },
+                            false => sleep(time::Duration::from_secs(1)),
+                        }
+                        continue;
+                    }
+                }
+            }
+        };
+        match cur_stream.next_events() {
+            Ok(events) => {
+                engine_stat.updater.account_events(num, &events);
+                apply_events(&updater, num, events);
+            }
+            Err(e) => {
+                // a broken stream is reopened, what came after the last commit is read again
+                log::warn!("source {} stream failed, reopen it: {}", named.name, e);
+                stream = None;
+                sleep(time::Duration::from_secs(1));
+            }
+        }
+    }
+    log::info!("slave thread of source {} finished", named.name);
+}
+
+fn apply_events(updater: &UpdaterPtr, num: usize, events: Vec<SourceEvent>) {
+    for event in events {
+        match event {
+            SourceEvent::Change {
+                ev_type,
+                object,
+                old,
+                origin,
+            } => object.apply(updater, old, ev_type, Some(origin)),
+            SourceEvent::Commit { position } => set_position(updater, num, position),
+        }
+    }
+}
+
+fn run_cron(updater: UpdaterPtr, core: usize) -> JoinHandle<()> {
     thread::Builder::new()
         .name(String::from("cron"))
         .spawn(move || {
-            helpers::bind_thread(1);
+            helpers::bind_thread(core);
             cron_loop(updater);
         })
         .expect("fail to run cron thread")
@@ -147,24 +881,44 @@ fn cron_loop(updater: UpdaterPtr) {
     let stop_flag = updater.read().unwrap().stop_flag.clone();
     let mut stop_checker = helpers::StopChecker::new(stop_flag);
     let mut last_swap_ts = 0;
+    let mut last_snapshot_ts = helpers::time::cur_ts();
 
     while !stop_checker.is_time() {
         let loop_start_ts = helpers::time::cur_ts();
 
+        finish_store_switch(&updater);
         if loop_start_ts > (last_swap_ts + updater.read().unwrap().conf.swap_interval) {
-            swap_stores(&updater);
+            let _ = swap_stores(&updater);
             last_swap_ts = loop_start_ts;
         }
+        let snapshot_interval = updater
+            .read()
+            .unwrap()
+            .conf
+            .snapshot
+            .as_ref()
+            .map(|s| s.interval);
+        if snapshot_interval.is_some_and(|interval| loop_start_ts > last_snapshot_ts + interval) {
+            let job = updater.read().unwrap().snapshot_job();
+            if let Some(job) = job {
+                job.run();
+            }
+            last_snapshot_ts = loop_start_ts;
+        }
         sleep(time::Duration::from_secs(1));
     }
 }
 
-pub fn apply_to_store<T: Storable + Clone + Debug + Sync + Send + 'static>(
+// origin is None for the initial load, such changes don't go to the history
+pub fn apply_to_store<T>(
     updater: &UpdaterPtr,
     obj: T,
     old_obj: Option<T>,
     ev_type: EventType,
-) {
+    origin: Option<EventOrigin>,
+) where
+    T: DbObject + StorableRaw + Storable + Serialize + Clone + Debug + Sync + Send + 'static,
+{
     log::debug!(
         "apply_to_store: action={:?}, old={:?}, old_obj={:?}",
         ev_type,
@@ -172,6 +926,31 @@ pub fn apply_to_store<T: Storable + Clone + Debug + Sync + Send + 'static>(
         old_obj
     );
 
+    let mut updater_w = updater.write().unwrap();
+    let updater = &mut *updater_w;
+
+    let obj_id = obj.get_id();
+    let old_key = old_obj.as_ref().map(|old| (T::table(), old.get_id()));
+    let history = &updater.engine_stat.history;
+    if let Some(origin) = origin.filter(|_| history.is_enabled()) {
+        let (old, new) = match ev_type {
+            EventType::Insert => (None, Some(&obj)),
+            EventType::Update => (old_obj.as_ref(), Some(&obj)),
+            EventType::Delete => (Some(&obj), None),
+        };
+        let change = Change {
+            event: ev_type.name(),
+            old: old.and_then(|o| serde_json::to_value(o).ok()),
+            new: new.and_then(|o| serde_json::to_value(o).ok()),
+            gtid: origin.gtid,
+            binlog_ts: origin.binlog_ts,
+            applied_ts: helpers::time::cur_ts(),
+            visible_iteration: updater.index_iteration + 1, // published by the next swap
+            visible_ts: None,
+        };
+        history.record((T::table(), obj_id), change);
+    }
+
     let apply_func = move |store: &mut Store| {
         match ev_type.clone() {
             EventType::Insert => {
@@ -186,9 +965,347 @@ pub fn apply_to_store<T: Storable + Clone + Debug + Sync + Send + 'static>(
         };
     };
 
-    let mut updater = updater.write().unwrap();
+    if let Some(reload_backlog) = updater.reload_backlog.as_mut() {
+        reload_backlog.push((Box::new(apply_func.clone()), Box::new(apply_func.clone())));
+    }
     updater.slave_updates.push(Box::new(apply_func.clone()));
+    let keys = old_key.into_iter().chain([(T::table(), obj_id)]);
+    if let Some(touched_keys) = updater.touched_keys.as_mut() {
+        touched_keys.extend(keys.clone());
+    }
+    updater.slave_updates_keys.extend(keys);
+
+    match updater.write_store.as_mut() {
+        Some(store) => apply_func(store),
+        None => updater.write_store_backlog.push(Box::new(apply_func)),
+    }
+    updater.update_backlog_stat();
+}
+
+impl UpdaterStat {
+    // lag by the last change of a batch, a batch without changes means the source is idle
+    fn account_events(&self, num: usize, events: &[SourceEvent]) {
+        let mut changes = 0;
+        let mut last_ts = None;
+        for event in events.iter() {
+            if let SourceEvent::Change { origin, .. } = event {
+                changes += 1;
+                last_ts = Some(origin.binlog_ts).filter(|ts| *ts != 0).or(last_ts);
+            }
+        }
+        let lag = last_ts.map_or(0, |ts| helpers::time::cur_ts().saturating_sub(ts));
+
+        let mut sources = self.sources.lock().unwrap();
+        if let Some(status) = sources.get_mut(num) {
+            status.lag_sec = lag;
+            status.changes += changes;
+        }
+        let max_lag = sources.iter().map(|s| s.lag_sec).max().unwrap_or(0);
+        self.replication_lag_sec.store(max_lag, Ordering::Relaxed);
+    }
+
+    pub fn write_metrics(&self, writer: &mut MetricsWriter) {
+        let name = "indexerd_store_swaps_total";
+        writer.family(name, "counter", "published store snapshots");
+        writer.sample(name, &[], self.store_swaps.load(Ordering::Relaxed));
+        let name = "indexerd_store_swap_duration_seconds";
+        writer.family(name, "histogram", "index rebuild and snapshot publish time");
+        writer.histogram(name, &[], &self.store_swap_duration);
+
+        for (name, help, value) in [
+            (
+                "indexerd_slave_updates_backlog",
+                "events applied since the last swap, replayed on the retired snapshot",
+                &self.slave_updates,
+            ),
+            (
+                "indexerd_write_store_backlog",
+                "events waiting for workers to release the retired snapshot",
+                &self.write_store_backlog,
+            ),
+            (
+                "indexerd_replication_lag_seconds",
+                "delay between a binlog event on the master and its processing",
+                &self.replication_lag_sec,
+            ),
+        ] {
+            writer.family(name, "gauge", help);
+            writer.sample(name, &[], value.load(Ordering::Relaxed));
+        }
+
+        let name = "indexerd_memory_limit_bytes";
+        writer.family(
+            name,
+            "gauge",
+            "updater.memory_limit_mb in bytes, 0 means no limit",
+        );
+        writer.sample(name, &[], self.memory_limit_bytes.load(Ordering::Relaxed));
+        let name = "indexerd_memory_limit_exceeded";
+        writer.family(
+            name,
+            "gauge",
+            "1 while estimated store memory is over the limit",
+        );
+        writer.sample(
+            name,
+            &[],
+            self.memory_limit_exceeded.load(Ordering::Relaxed) as u8,
+        );
+
+        let sources = self.sources.lock().unwrap();
+        let name = "indexerd_source_lag_seconds";
+        writer.family(name, "gauge", "delay of the last applied change by source");
+        for status in sources.iter() {
+            writer.sample(name, &[("source", status.name.as_str())], status.lag_sec);
+        }
+        let name = "indexerd_source_changes_total";
+        writer.family(name, "counter", "changes received from the source");
+        for status in sources.iter() {
+            writer.sample(name, &[("source", status.name.as_str())], status.changes);
+        }
+        drop(sources);
+
+        let name = "indexerd_binlog_events_total";
+        writer.family(name, "counter", "processed binlog events by type");
+        for (ev_type, value) in BINLOG_EVENT_TYPES.iter().zip(self.binlog_events.iter()) {
+            writer.sample(name, &[("type", ev_type)], value.load(Ordering::Relaxed));
+        }
+    }
+}
+
+impl Default for UpdaterStat {
+    fn default() -> Self {
+        UpdaterStat {
+            store_swaps: AtomicU64::new(0),
+            store_swap_duration: Histogram::new(SWAP_BUCKETS),
+            slave_updates: AtomicU64::new(0),
+            write_store_backlog: AtomicU64::new(0),
+            replication_lag_sec: AtomicU64::new(0),
+            binlog_events: Default::default(),
+            write_store_memory: Mutex::new(StoreMemory::default()),
+            memory_limit_bytes: AtomicU64::new(0),
+            memory_limit_exceeded: AtomicBool::new(false),
+            sources: Mutex::new(Vec::new()),
+            load: Mutex::new(LoadProgress::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::objects::{Campaign, Package};
+    use crate::data::source::SourceObject;
+
+    // canned objects and a single batch of changes, no database involved;
+    // the first stream breaks before giving anything
+    struct TestSource {
+        streamed_from: Arc<Mutex<Vec<Option<String>>>>,
+    }
+
+    struct TestStream {
+        broken: bool,
+        events: Vec<SourceEvent>,
+    }
+
+    impl ChangeSource for TestSource {
+        fn name(&self) -> String {
+            String::from("test")
+        }
 
-    let mut store_locked = updater.write_store.write().unwrap();
-    apply_func(store_locked.deref_mut());
+        fn position(&self) -> Result<Option<String>, Box<dyn Error>> {
+            Ok(Some(String::from("1")))
+        }
+
+        // keeps changes from 1 on
+        fn can_resume(&self, position: &str) -> Result<bool, Box<dyn Error>> {
+            Ok(position.parse::<u64>()? >= 1)
+        }
+
+        fn tables(&self) -> Vec<&'static str> {
+            vec!["campaign", "package"]
+        }
+
+        fn load_table(
+            &self,
+            table: &str,
+            _chunk_size: usize,
+            f: &mut dyn FnMut(Vec<SourceObject>),
+        ) -> Result<(), Box<dyn Error>> {
+            let object: SourceObject = match table {
+                "campaign" => Campaign {
+                    id: 1,
+                    package_id: 10,
+                    ..Default::default()
+                }
+                .into(),
+                _ => Package {
+                    id: 10,
+                    ..Default::default()
+                }
+                .into(),
+            };
+            f(vec![object]);
+            Ok(())
+        }
+
+        fn stream(
+            &self,
+            position: Option<String>,
+        ) -> Result<Box<dyn ChangeStream>, Box<dyn Error>> {
+            let mut streamed_from = self.streamed_from.lock().unwrap();
+            streamed_from.push(position);
+            Ok(Box::new(TestStream {
+                broken: streamed_from.len() == 1,
+                events: vec![
+                    SourceEvent::Change {
+                        ev_type: EventType::Insert,
+                        object: Campaign {
+                            id: 2,
+                            ..Default::default()
+                        }
+                        .into(),
+                        old: None,
+                        origin: EventOrigin::default(),
+                    },
+                    SourceEvent::Commit {
+                        position: String::from("2"),
+                    },
+                ],
+            }))
+        }
+    }
+
+    impl ChangeStream for TestStream {
+        fn next_events(&mut self) -> Result<Vec<SourceEvent>, Box<dyn Error>> {
+            if self.broken {
+                return Err("connection lost".into());
+            }
+            if self.events.is_empty() {
+                sleep(time::Duration::from_millis(10));
+            }
+            Ok(self.events.drain(..).collect())
+        }
+    }
+
+    #[test]
+    fn test_updater_with_source() {
+        let (_task_snd, task_rcv) = crossbeam_channel::unbounded();
+        let (server_snd, _server_rcv) = crossbeam_channel::unbounded();
+        let engine = Arc::new(RwLock::new(engine::Engine::new(
+            &config::Engine::default(),
+            &config::CoreAssignment::default(),
+            task_rcv,
+            None,
+            server_snd,
+        )));
+        let conf = config::Updater {
+            swap_interval: u64::MAX / 2, // swaps are driven by the test
+            ..Default::default()
+        };
+        let streamed_from = Arc::new(Mutex::new(Vec::new()));
+        let source = Arc::new(TestSource {
+            streamed_from: streamed_from.clone(),
+        });
+        let updater = Updater::with_sources(
+            &conf,
+            &config::CoreAssignment::default(),
+            engine.clone(),
+            vec![NamedSource {
+                name: String::from("test"),
+                source,
+            }],
+        )
+        .unwrap();
+        // the initial load is served without waiting for a swap
+        let initial = engine.read().unwrap().store();
+        assert_eq!(1, initial.get_store_stat().iteration);
+        assert_eq!(vec![1], initial.get_raw_data().list::<Campaign>());
+        let load = engine
+            .read()
+            .unwrap()
+            .stat()
+            .updater
+            .load
+            .lock()
+            .unwrap()
+            .clone();
+        assert!(load.finished_ts.is_some() && load.tables.iter().all(|t| t.done && t.rows == 1));
+
+        let applied = (0..500).any(|_| {
+            sleep(time::Duration::from_millis(10));
+            updater
+                .read()
+                .unwrap()
+                .positions
+                .get("test")
+                .map(String::as_str)
+                == Some("2")
+        });
+        assert!(applied);
+        // reopened from the same position after the break
+        let reopened = vec![Some(String::from("1")); 2];
+        assert_eq!(reopened, *streamed_from.lock().unwrap());
+
+        let report = swap_stores(&updater).unwrap();
+        assert_eq!(2, report.iteration);
+        let published = engine.read().unwrap().store();
+        let mut campaigns = published.get_raw_data().list::<Campaign>();
+        campaigns.sort();
+        assert_eq!(vec![1, 2], campaigns);
+        assert_eq!(1, published.get_raw_data().list::<Package>().len());
+        let published_positions = updater.read().unwrap().published_positions.clone();
+        assert_eq!("2", published_positions.unwrap()["test"]);
+        let stat = engine.read().unwrap().stat();
+        let sources = stat.updater.sources.lock().unwrap().clone();
+        assert_eq!(
+            (1, Some("2")),
+            (sources[0].changes, sources[0].position.as_deref())
+        );
+        // only streamed changes have an origin
+        let history = &engine.read().unwrap().stat().history;
+        assert_eq!(1, history.get(("campaign", 2)).len());
+        assert!(history.get(("campaign", 1)).is_empty());
+
+        // a full reload starts over from where the sources are before the load
+        let report = full_reload(&updater).unwrap();
+        assert_eq!(3, report.iteration);
+        let published_positions = updater.read().unwrap().published_positions.clone();
+        assert_eq!("1", published_positions.unwrap()["test"]);
+        assert_eq!("1", updater.read().unwrap().positions["test"]);
+        assert!(updater.read().unwrap().reload_backlog.is_none());
+        let check = check_consistency(&updater, true).unwrap();
+        assert!(check.consistent);
+        assert_eq!("1", check.db_positions.unwrap()["test"]);
+        assert!(updater.read().unwrap().touched_keys.is_none());
+        assert!(updater.read().unwrap().write_store.is_some());
+
+        stop(&updater);
+        engine.write().unwrap().stop();
+    }
+
+    #[test]
+    fn test_snapshot_needs_resumable_positions() {
+        let path = std::env::temp_dir().join(format!("indexerd-warm-{}", std::process::id()));
+        let conf = config::Snapshot {
+            path: path.to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let sources = vec![NamedSource {
+            name: String::from("test"),
+            source: Arc::new(TestSource {
+                streamed_from: Default::default(),
+            }),
+        }];
+        let mut store = Store::default();
+        store.rebuild_index(3);
+
+        for (position, resumed) in [("0", false), ("1", true)] {
+            let positions = Positions::from([(String::from("test"), String::from(position))]);
+            snapshot::save(&conf.path, &store, positions).unwrap();
+            let loaded = load_snapshot(&conf, &sources);
+            assert_eq!(resumed, loaded.is_some(), "position {}", position);
+        }
+        let _ = std::fs::remove_file(&path);
+    }
 }
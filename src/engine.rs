@@ -1,88 +1,197 @@
 extern crate crossbeam_channel;
 extern crate log;
 
+use crate::data::history::ChangeHistory;
 use crate::data::store::Store;
+use crate::data::updater::UpdaterStat;
+use crate::metrics::{Histogram, MetricsWriter};
+use crate::reload::ServerRequest;
 use crate::task::HttpTask;
 
-use crate::worker::{ControlTask, WorkerData};
+use crate::worker::{ControlTask, WorkerData, WorkerRole};
 use crate::{config, worker};
+use arc_swap::ArcSwap;
 use crossbeam_channel::{Receiver, Sender};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, RwLock};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+// published read-only snapshot; workers load it per request without locking
+pub type StorePtr = Arc<ArcSwap<Store>>;
 
 pub struct Engine {
-    store: Arc<RwLock<Store>>,
+    store: StorePtr,
     shutdown_workers: Arc<AtomicBool>,
     workers: Vec<JoinHandle<()>>,
     ctl_queues: Vec<Sender<ControlTask>>,
+    stat: Arc<EngineStat>,
     conf: config::Engine,
 }
 
+#[derive(Default)]
+pub struct WorkerStat {
+    pub busy: AtomicBool,
+    pub store_iteration: AtomicU64, // iteration of the last store snapshot the worker loaded
+    pub slow_store_switches: AtomicU64,
+    pub requests: Vec<Histogram>, // per route, same order as EngineStat::routes
+}
+
+#[derive(Default)]
+pub struct EngineStat {
+    pub workers: Vec<WorkerStat>,
+    pub last_store_switch_ms: AtomicU64,
+    pub store_switch_timeouts: AtomicU64,
+    pub rejected_requests: AtomicU64, // task queue was full
+    pub expired_requests: AtomicU64,  // deadline passed while waiting in task queue
+    pub routes: Vec<&'static str>,
+    pub task_queues: Vec<(&'static str, QueueDepth)>, // search, then admin if it has its own
+    pub ctl_queues: Vec<QueueDepth>,                  // by worker
+    pub updater: UpdaterStat,
+    pub history: ChangeHistory,
+}
+
+// counted by whoever sends and receives, holding channel ends just to call len()
+// would keep the channels connected after their owners are gone
+#[derive(Default)]
+pub struct QueueDepth(AtomicU64);
+
+impl QueueDepth {
+    // before sending, undone by pop if the send fails
+    pub fn push(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn pop(&self) {
+        let _ = self
+            .0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |d| d.checked_sub(1));
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Default, Clone, Deserialize, Serialize)]
+pub struct EngineStatSnapshot {
+    pub workers_store_iteration: Vec<u64>,
+    pub workers_slow_store_switches: Vec<u64>,
+    pub last_store_switch_ms: u64,
+    pub store_switch_timeouts: u64,
+    pub rejected_requests: u64,
+    pub expired_requests: u64,
+}
+
 impl Engine {
-    pub fn new(conf: &config::Engine, task_queue_rcv: Receiver<HttpTask>) -> Self {
+    // admin_queue_rcv is set when admin has its own listener, it gets a dedicated worker then
+    pub fn new(
+        conf: &config::Engine,
+        cores: &config::CoreAssignment,
+        task_queue_rcv: Receiver<HttpTask>,
+        admin_queue_rcv: Option<Receiver<HttpTask>>,
+        server_queue: Sender<ServerRequest>,
+    ) -> Self {
+        let search_role = match admin_queue_rcv {
+            Some(_) => WorkerRole::Search,
+            None => WorkerRole::Mixed,
+        };
+        let mut workers = cores
+            .workers
+            .iter()
+            .map(|core| (search_role, *core, task_queue_rcv.clone()))
+            .collect::<Vec<_>>();
+        if let Some(admin_queue_rcv) = admin_queue_rcv {
+            // admin is off the hot path, it shares the core with cron
+            workers.push((WorkerRole::Admin, cores.cron, admin_queue_rcv));
+        }
+
+        let mut task_queues = vec![("search", QueueDepth::default())];
+        if let Some((WorkerRole::Admin, _, _)) = workers.last() {
+            task_queues.push(("admin", QueueDepth::default()));
+        }
+        let (ctl_queues, ctl_queues_rcv): (Vec<Sender<ControlTask>>, Vec<Receiver<ControlTask>>) =
+            workers
+                .iter()
+                .map(|_| crossbeam_channel::bounded(conf.ctl_queue_size))
+                .unzip();
+        let routes = worker::route_names();
+
         let mut engine = Engine {
-            store: Arc::new(RwLock::new(Store::default())),
+            store: Arc::new(ArcSwap::from_pointee(Store::default())),
             shutdown_workers: Arc::new(AtomicBool::new(false)),
             workers: Vec::new(),
-            ctl_queues: Vec::new(),
+            ctl_queues,
+            stat: Arc::new(EngineStat {
+                workers: workers
+                    .iter()
+                    .map(|_| WorkerStat {
+                        requests: routes.iter().map(|_| Histogram::default()).collect(),
+                        ..Default::default()
+                    })
+                    .collect(),
+                routes,
+                task_queues,
+                ctl_queues: workers.iter().map(|_| QueueDepth::default()).collect(),
+                ..Default::default()
+            }),
             conf: conf.clone(),
         };
 
-        for worker_num in 0..=1 {
-            let (ctl_queue_snd, ctl_queue_rcv): (Sender<ControlTask>, Receiver<ControlTask>) =
-                crossbeam_channel::bounded(1000);
-
+        let worker_queues = workers.into_iter().zip(ctl_queues_rcv);
+        for (worker_num, ((role, core, task_queue), ctl_queue_rcv)) in worker_queues.enumerate() {
             let worker_data = WorkerData {
-                num: worker_num,
-                task_queue: task_queue_rcv.clone(),
+                num: worker_num as i32,
+                role,
+                task_queue,
                 ctl_task_queue: ctl_queue_rcv,
                 store: engine.store.clone(),
                 config: engine.conf.worker,
+                server_queue: server_queue.clone(),
+                engine_stat: engine.stat.clone(),
             };
 
-            let th = worker::run(worker_data, engine.shutdown_workers.clone());
+            let th = worker::run(worker_data, core, engine.shutdown_workers.clone());
             engine.workers.push(th);
-            engine.ctl_queues.push(ctl_queue_snd);
-            log::info!("worker {} started", worker_num);
+            log::info!(
+                "worker {} ({:?}) started on core {}",
+                worker_num,
+                role,
+                core
+            );
         }
         engine
     }
 
-    pub fn set_new_store(&mut self, store: &Arc<RwLock<Store>>) {
+    // returns the previous snapshot, see EngineStat::wait_store_release
+    pub fn set_new_store(&mut self, store: Arc<Store>) -> Arc<Store> {
         log::info!(
-            "set_new_store: {}",
-            store.read().unwrap().get_store_stat().iteration
+            "set_new_store: iteration={}",
+            store.get_store_stat().iteration
         );
-        self.store = store.clone();
-        // let counter = Arc::new(AtomicUsize::new(0));
-        for queue in self.ctl_queues.iter_mut() {
-            let store_copy = self.store.clone();
-            // let counter_copy = counter.clone();
-            let func = move |worker_data: &mut WorkerData| {
-                worker_data.store = store_copy;
-                // counter_copy.store(
-                //     counter_copy.load(Ordering::Relaxed).add(1),
-                //     Ordering::Relaxed,
-                // );
-            };
-            if let Err(e) = queue.send(Box::new(func)) {
-                log::warn!("Fail to add task to ctl_queue: {}", e);
-            }
-        }
-        // wait until all workers get new store
-        // while counter.load(Ordering::Relaxed) < self.workers.len() {}
+        self.store.swap(store)
+    }
+
+    // currently published snapshot
+    pub fn store(&self) -> Arc<Store> {
+        self.store.load_full()
+    }
+
+    pub fn stat(&self) -> Arc<EngineStat> {
+        self.stat.clone()
     }
 
-    #[allow(dead_code)]
     pub fn update_config(&mut self, conf: config::Engine) {
         self.conf = conf;
-        for queue in self.ctl_queues.iter_mut() {
+        for (queue, depth) in self.ctl_queues.iter_mut().zip(self.stat.ctl_queues.iter()) {
             let conf_copy = self.conf.worker;
             let func = move |worker_data: &mut WorkerData| {
                 worker_data.config = conf_copy;
             };
+            depth.push();
             if let Err(e) = queue.send(Box::new(func)) {
+                depth.pop();
                 log::warn!("Fail to add task to ctl_queue: {:?}", e);
             }
         }
@@ -97,3 +206,137 @@ impl Engine {
         log::info!("engine stopped");
     }
 }
+
+impl EngineStat {
+    pub fn task_queue_depth(&self, queue: &str) -> Option<&QueueDepth> {
+        self.task_queues
+            .iter()
+            .find(|(name, _)| *name == queue)
+            .map(|(_, depth)| depth)
+    }
+
+    // workers still processing a request with the store of given iteration
+    pub fn lagging_workers(&self, iteration: u64) -> Vec<usize> {
+        self.workers
+            .iter()
+            .enumerate()
+            .filter(|(_, w)| {
+                w.busy.load(Ordering::Acquire)
+                    && w.store_iteration.load(Ordering::Acquire) == iteration
+            })
+            .map(|(num, _)| num)
+            .collect()
+    }
+
+    // blocks until nobody but the caller references the retired snapshot;
+    // returns workers still using it on timeout
+    pub fn wait_store_release(
+        &self,
+        retired: &Arc<Store>,
+        timeout: Duration,
+    ) -> Result<(), Vec<usize>> {
+        let start = Instant::now();
+        loop {
+            if Arc::strong_count(retired) == 1 {
+                let elapsed = start.elapsed().as_millis() as u64;
+                self.last_store_switch_ms.store(elapsed, Ordering::Relaxed);
+                return Ok(());
+            }
+            if start.elapsed() >= timeout {
+                let lagging = self.lagging_workers(retired.get_store_stat().iteration);
+                self.store_switch_timeouts.fetch_add(1, Ordering::Relaxed);
+                for num in lagging.iter() {
+                    self.workers[*num]
+                        .slow_store_switches
+                        .fetch_add(1, Ordering::Relaxed);
+                }
+                return Err(lagging);
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    pub fn observe_request(&self, worker_num: usize, route: &str, duration: Duration) {
+        // a handful of routes, linear search is cheaper than hashing
+        if let Some(pos) = self.routes.iter().position(|r| *r == route) {
+            self.workers[worker_num].requests[pos].observe(duration);
+        }
+    }
+
+    pub fn write_metrics(&self, writer: &mut MetricsWriter) {
+        let name = "indexerd_request_duration_seconds";
+        writer.family(
+            name,
+            "histogram",
+            "request handling time by route and worker",
+        );
+        for (num, worker) in self.workers.iter().enumerate() {
+            let worker_num = num.to_string();
+            for (route, histogram) in self.routes.iter().zip(worker.requests.iter()) {
+                let labels = [("route", *route), ("worker", worker_num.as_str())];
+                writer.histogram(name, &labels, histogram);
+            }
+        }
+
+        let name = "indexerd_task_queue_depth";
+        writer.family(name, "gauge", "requests waiting for a worker");
+        for (queue, depth) in self.task_queues.iter() {
+            writer.sample(name, &[("queue", queue)], depth.get());
+        }
+        let name = "indexerd_ctl_queue_depth";
+        writer.family(name, "gauge", "control tasks waiting for a worker");
+        for (num, depth) in self.ctl_queues.iter().enumerate() {
+            writer.sample(name, &[("worker", num.to_string().as_str())], depth.get());
+        }
+
+        for (name, help, value) in [
+            (
+                "indexerd_rejected_requests_total",
+                "requests rejected with 503 because the task queue was full",
+                &self.rejected_requests,
+            ),
+            (
+                "indexerd_expired_requests_total",
+                "requests dropped after their deadline passed in the task queue",
+                &self.expired_requests,
+            ),
+            (
+                "indexerd_store_switch_timeouts_total",
+                "store swaps where workers didn't release the old snapshot in time",
+                &self.store_switch_timeouts,
+            ),
+        ] {
+            writer.family(name, "counter", help);
+            writer.sample(name, &[], value.load(Ordering::Relaxed));
+        }
+        let name = "indexerd_last_store_switch_seconds";
+        writer.family(
+            name,
+            "gauge",
+            "time workers took to release the old snapshot",
+        );
+        let last_switch_ms = self.last_store_switch_ms.load(Ordering::Relaxed);
+        writer.sample(name, &[], last_switch_ms as f64 / 1000.0);
+
+        self.updater.write_metrics(writer);
+    }
+
+    pub fn snapshot(&self) -> EngineStatSnapshot {
+        EngineStatSnapshot {
+            workers_store_iteration: self
+                .workers
+                .iter()
+                .map(|w| w.store_iteration.load(Ordering::Relaxed))
+                .collect(),
+            workers_slow_store_switches: self
+                .workers
+                .iter()
+                .map(|w| w.slow_store_switches.load(Ordering::Relaxed))
+                .collect(),
+            last_store_switch_ms: self.last_store_switch_ms.load(Ordering::Relaxed),
+            store_switch_timeouts: self.store_switch_timeouts.load(Ordering::Relaxed),
+            rejected_requests: self.rejected_requests.load(Ordering::Relaxed),
+            expired_requests: self.expired_requests.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -1,134 +1,322 @@
-use crate::data::objects::{Campaign, IdType, Package, Pad};
-use crate::data::objects_traits::MysqlObject;
-use crate::data::slave;
+use crate::data::explain;
+use crate::data::memory::StoreMemory;
+use crate::data::objects::IdType;
+use crate::data::query::{self, ObjectQuery};
+use crate::data::registry;
+use crate::data::source::{LoadProgress, SourceStatus};
 use crate::data::store::IndexStat;
-use crate::task::AdminTask;
+use crate::engine::EngineStatSnapshot;
+use crate::helpers;
+use crate::reload::{ActionRequest, AdminAction, ReloadRequest, ServerRequest};
+use crate::router::{HttpError, PathParams, QueryParams};
+use crate::task::{AdminTask, HttpTask};
+use crate::templates;
+use crossbeam_channel::Sender;
 use serde::{Deserialize, Serialize};
-use tera::{Error, Tera};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
 
-// static my_str: &str = include_str!("$CARGO_MANIFEST_DIR/src/html_tpl/admin.html");
+pub type Handler = fn(&AdminTask, &PathParams) -> Result<String, HttpError>;
 
-pub fn handle(task: AdminTask) {
+// admin pages, /admin/config/reload is routed by the worker since it doesn't need the store
+pub fn routes() -> Vec<(&'static str, Handler)> {
+    vec![
+        ("/admin", handle_root),
+        ("/admin/status", handle_status),
+        ("/admin/explain", handle_explain), // /admin/explain?pad_id=1&campaign_id=2
+        ("/admin/find", handle_find),       // /admin/find?id=1, looks in every table
+        ("/admin/store", handle_store),
+        ("/admin/store/:table", handle_objects),
+        ("/admin/store/:table/:id", handle_object_detailed),
+    ]
+}
+
+pub fn handle(task: AdminTask, handler: Handler, params: &PathParams) {
     log::debug!("start handing admin task: {}", task.http_task.url());
-    let url = task.http_task.url();
-    let _ = url.strip_suffix('/');
-    let html_response = {
-        if url.eq("/admin") {
-            handle_root(&task)
-        } else if url.eq("/admin/status") {
-            handle_status(&task)
-        } else if url.eq("/admin/store") {
-            handle_store(&task)
-        } else if url.starts_with("/admin/store/") {
-            let paths = url.split('/').collect::<Vec<&str>>();
-            if paths.len() == 4 {
-                // /admin/store/campaign
-                handle_objects(&task, paths[3])
-            } else {
-                // /admin/store/campaign/1
-                handle_object_detailed(&task, paths[3], paths[4].parse::<i32>().unwrap())
-            }
-        } else {
-            Ok(format!("unknown url: {}", url))
+    match handler(&task, params) {
+        Ok(rsp) => task.http_task.respond_html(&rsp),
+        Err(e) => {
+            log::debug!("admin task failed: {} {}", e.code, e.message);
+            task.http_task.respond_error(&e)
         }
-    };
+    }
+}
 
-    match html_response {
-        Ok(rsp) => task.http_task.respond_html(&rsp),
-        Err(e) => task
-            .http_task
-            .respond_html(&format!("handling error: {:?}", e)),
+#[derive(Deserialize, Serialize)]
+pub struct Status {
+    is_ready: bool,
+    index_stat: IndexStat, // memory of the published store included
+    engine_stat: EngineStatSnapshot,
+    write_store_memory: StoreMemory,
+    memory_limit_bytes: u64,
+    memory_limit_exceeded: bool,
+    sources: Vec<SourceStatus>, // position, lag and changes of every replication source
+    load: LoadProgress,         // of the last load from the sources
+}
+
+pub fn status(task: &AdminTask) -> Status {
+    let index_stat = task.context.store.get_store_stat().clone();
+    let updater_stat = &task.context.engine_stat.updater;
+    let memory_limit_exceeded = updater_stat.memory_limit_exceeded.load(Ordering::Relaxed);
+    Status {
+        is_ready: index_stat.iteration != 0 && !memory_limit_exceeded,
+        index_stat,
+        engine_stat: task.context.engine_stat.snapshot(),
+        write_store_memory: updater_stat.write_store_memory.lock().unwrap().clone(),
+        memory_limit_bytes: updater_stat.memory_limit_bytes.load(Ordering::Relaxed),
+        memory_limit_exceeded,
+        sources: updater_stat.sources.lock().unwrap().clone(),
+        load: updater_stat.load.lock().unwrap().clone(),
     }
 }
 
-fn handle_root(_: &AdminTask) -> Result<String, Error> {
-    let tpl_name = "admin.html";
-    let tpl_data = include_str!("../html_tpl/admin.html");
-    let mut tera = Tera::default();
-    tera.add_raw_template(tpl_name, tpl_data)?;
-    let context = tera::Context::new();
-    tera.render(tpl_name, &context)
+// pad_id and campaign_id from query string, 0 when not given
+pub fn explain_params(task: &AdminTask) -> Result<(IdType, IdType), HttpError> {
+    let query = QueryParams::from_url(task.http_task.url());
+    Ok((
+        query.parse_or("pad_id", 0)?,
+        query.parse_or("campaign_id", 0)?,
+    ))
 }
 
-fn handle_status(task: &AdminTask) -> Result<String, Error> {
-    #[derive(Deserialize, Serialize)]
-    struct Status {
-        is_ready: bool,
-        index_stat: IndexStat,
+// ?offset=0&limit=100&sort=-id&<field>=<value>, unknown params are ignored
+pub fn object_query(
+    task: &AdminTask,
+    object_meta: &registry::ObjectType,
+) -> Result<ObjectQuery, HttpError> {
+    let params = QueryParams::from_url(task.http_task.url());
+    let query = ObjectQuery {
+        filters: params
+            .iter()
+            .filter(|(k, _)| object_meta.fields.contains(k))
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+        sort: params
+            .get("sort")
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string()),
+        offset: params.parse_or("offset", 0)?,
+        limit: params
+            .parse_or("limit", query::DEFAULT_PAGE_SIZE)?
+            .min(query::MAX_PAGE_SIZE),
+    };
+    if let Some((field, _)) = query.sort_field() {
+        if !object_meta.fields.contains(&field) {
+            let msg = format!("unknown sort field: {}", field);
+            return Err(HttpError::bad_request(msg.as_str()));
+        }
     }
+    Ok(query)
+}
 
-    let mut status = Status {
-        is_ready: false,
-        index_stat: task.context.store.get_store_stat().clone(),
+// POST /admin/config/reload, authenticated like actions
+pub fn handle_config_reload(http_task: HttpTask, server_queue: &Sender<ServerRequest>) {
+    let (reply_snd, reply_rcv) = crossbeam_channel::bounded(1);
+    let request = ReloadRequest {
+        token: bearer_token(&http_task),
+        reply: reply_snd,
     };
-    status.is_ready = status.index_stat.iteration != 0;
-    Ok(serde_json::to_string(&status).unwrap_or("fail to deserialize".to_string()))
+    let result = match server_queue.send(ServerRequest::ReloadConfig(request)) {
+        Ok(_) => reply_rcv
+            .recv_timeout(Duration::from_secs(10))
+            .unwrap_or_else(|e| {
+                let msg = format!("no reply from reloader: {}", e);
+                Err(HttpError::new(504, msg.as_str()))
+            }),
+        Err(e) => Err(HttpError::internal(
+            format!("fail to send reload request: {}", e).as_str(),
+        )),
+    };
+    match result {
+        Ok(diff) => http_task.respond_json(
+            serde_json::to_string(&diff)
+                .unwrap_or("fail to serialize".to_string())
+                .as_str(),
+        ),
+        Err(e) => http_task.respond_json_error(&e),
+    }
+}
+
+fn bearer_token(http_task: &HttpTask) -> Option<String> {
+    http_task
+        .header("Authorization")
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|t| t.trim().to_string())
+}
+
+// POST /admin/actions/..., authenticated with 'Authorization: Bearer <token>'
+pub fn handle_action(
+    http_task: HttpTask,
+    action: AdminAction,
+    server_queue: &Sender<ServerRequest>,
+) {
+    let token = bearer_token(&http_task);
+    let (reply_snd, reply_rcv) = crossbeam_channel::bounded(1);
+    let request = ActionRequest {
+        action,
+        token,
+        reply: reply_snd,
+    };
+    let result = match server_queue.send(ServerRequest::Action(request)) {
+        Ok(_) => reply_rcv
+            .recv_timeout(action.reply_timeout())
+            .unwrap_or_else(|e| Err(HttpError::internal(format!("no reply: {}", e).as_str()))),
+        Err(e) => Err(HttpError::internal(format!("fail to send: {}", e).as_str())),
+    };
+    match result {
+        Ok(report) => {
+            let response = serde_json::json!({ "action": action.name(), "result": report });
+            http_task.respond_json(response.to_string().as_str())
+        }
+        Err(e) => http_task.respond_json_error(&e),
+    }
+}
+
+fn handle_root(_: &AdminTask, _: &PathParams) -> Result<String, HttpError> {
+    let tpl_name = "admin.html";
+    let context = tera::Context::new();
+    Ok(templates::render(tpl_name, &context)?)
+}
+
+fn handle_status(task: &AdminTask, _: &PathParams) -> Result<String, HttpError> {
+    Ok(serde_json::to_string(&status(task)).unwrap_or("fail to deserialize".to_string()))
 }
 
-fn handle_store(_task: &AdminTask) -> Result<String, Error> {
-    let tpl_name = "tpl";
-    let tpl_data = include_str!("../html_tpl/admin_store.html");
-    let mut tera = Tera::default();
-    tera.add_raw_template(tpl_name, tpl_data)?;
+fn handle_store(_task: &AdminTask, _: &PathParams) -> Result<String, HttpError> {
+    let tpl_name = "admin_store.html";
     let mut context = tera::Context::new();
-    context.insert(
-        "objects",
-        &vec![Campaign::table(), Package::table(), Pad::table()],
-    );
+    let tables = registry::object_types()
+        .iter()
+        .map(|t| t.table)
+        .collect::<Vec<_>>();
+    context.insert("objects", &tables);
 
-    tera.render(tpl_name, &context)
+    Ok(templates::render(tpl_name, &context)?)
 }
 
-fn handle_objects(task: &AdminTask, object_type: &str) -> Result<String, Error> {
-    let store_row_data = task.context.store.get_raw_data();
-    let mut objects = match slave::SupportedTypes::from(object_type) {
-        slave::SupportedTypes::Campaign => store_row_data.list::<Campaign>(),
-        slave::SupportedTypes::Package => store_row_data.list::<Package>(),
-        slave::SupportedTypes::Pad => store_row_data.list::<Pad>(),
-        _ => return Err("unknown object type".into()),
+fn handle_objects(task: &AdminTask, params: &PathParams) -> Result<String, HttpError> {
+    let object_type = params.get("table");
+    let object_meta = registry::find(object_type).ok_or_else(|| unknown_table(object_type))?;
+    let query = object_query(task, &object_meta)?;
+    let result = query::run(&object_meta, task.context.store.get_raw_data(), &query);
+
+    let filters = object_meta
+        .fields
+        .iter()
+        .map(|field| {
+            let value = query.filters.iter().find(|(k, _)| k == field);
+            serde_json::json!({ "name": field, "value": value.map(|(_, v)| v) })
+        })
+        .collect::<Vec<_>>();
+    let page_url = |offset: usize| {
+        let mut url = url::form_urlencoded::Serializer::new(String::new());
+        url.extend_pairs(query.filters.iter());
+        if let Some(sort) = query.sort.as_ref() {
+            url.append_pair("sort", sort);
+        }
+        url.append_pair("limit", query.limit.to_string().as_str());
+        url.append_pair("offset", offset.to_string().as_str());
+        format!("/admin/store/{}?{}", object_type, url.finish())
     };
-    objects.sort();
+    let prev_url = (query.offset > 0).then(|| page_url(query.offset.saturating_sub(query.limit)));
+    let next_url =
+        (query.offset + query.limit < result.total).then(|| page_url(query.offset + query.limit));
 
-    let tpl_name = "tpl";
-    let tpl_data = include_str!("../html_tpl/admin_store_objects.html");
-    let mut tera = Tera::default();
-    tera.add_raw_template(tpl_name, tpl_data)?;
+    let tpl_name = "admin_store_objects.html";
     let mut context = tera::Context::new();
     context.insert("object_type", object_type);
-    context.insert("objects", &objects);
+    context.insert("fields", object_meta.fields);
+    context.insert("filters", &filters);
+    context.insert("query", &query);
+    context.insert("result", &result);
+    context.insert("prev_url", &prev_url);
+    context.insert("next_url", &next_url);
 
-    tera.render(tpl_name, &context)
+    Ok(templates::render(tpl_name, &context)?)
 }
 
-fn handle_object_detailed(
-    task: &AdminTask,
-    object_type: &str,
-    object_id: IdType,
-) -> Result<String, Error> {
+fn handle_find(task: &AdminTask, _: &PathParams) -> Result<String, HttpError> {
+    let id = QueryParams::from_url(task.http_task.url()).parse_or("id", 0)?;
+    let tables = match id {
+        0 => Vec::new(),
+        id => registry::find_by_id(task.context.store.get_raw_data(), id),
+    };
+
+    let tpl_name = "admin_find.html";
+    let mut context = tera::Context::new();
+    context.insert("id", &id);
+    context.insert("tables", &tables);
+    Ok(templates::render(tpl_name, &context)?)
+}
+
+fn handle_explain(task: &AdminTask, _: &PathParams) -> Result<String, HttpError> {
+    let (pad_id, campaign_id) = explain_params(task)?;
+
+    let explain = explain::explain(
+        task.context.store.get_raw_data(),
+        pad_id,
+        campaign_id,
+        helpers::time::cur_ts() as i64,
+    );
+
+    let tpl_name = "admin_explain.html";
+    let mut context = tera::Context::new();
+    context.insert("campaign_id", &campaign_id);
+    context.insert("explain", &explain);
+    Ok(templates::render(tpl_name, &context)?)
+}
+
+fn handle_object_detailed(task: &AdminTask, params: &PathParams) -> Result<String, HttpError> {
+    let object_type = params.get("table");
+    let object_id = params.parse::<IdType>("id")?;
     let store_rd = task.context.store.get_raw_data();
 
-    let tpl_name = "tpl";
-    let tpl_data = include_str!("../html_tpl/admin_store_objects_detailed.html");
-    let mut tera = Tera::default();
-    tera.add_raw_template(tpl_name, tpl_data)?;
+    let tpl_name = "admin_store_objects_detailed.html";
     let mut context = tera::Context::new();
     context.insert("object_type", object_type);
 
-    match slave::SupportedTypes::from(object_type) {
-        slave::SupportedTypes::Campaign => match store_rd.try_get::<Campaign>(object_id) {
-            Some(campaign) => context.insert("object", campaign.html_debug().as_str()),
-            None => context.insert("object", "campaign not found"),
-        },
-        slave::SupportedTypes::Package => context.insert(
-            "object",
-            format!("{:?}", store_rd.try_get::<Package>(object_id)).as_str(),
-        ),
-        slave::SupportedTypes::Pad => context.insert(
-            "object",
-            format!("{:?}", store_rd.try_get::<Pad>(object_id)).as_str(),
-        ),
-        _ => return Err("unknown object type".into()),
-    };
+    let object_meta = registry::find(object_type).ok_or_else(|| unknown_table(object_type))?;
+    let history = task
+        .context
+        .engine_stat
+        .history
+        .get((object_meta.table, object_id));
+    // deleted objects are still shown while their history is kept
+    let object = (object_meta.to_json)(store_rd, object_id);
+    if object.is_none() && history.is_empty() {
+        return Err(HttpError::not_found(
+            format!("{} {} not found", object_type, object_id).as_str(),
+        ));
+    }
+    let object = object.unwrap_or_default();
+    let links = registry::forward_links(&object_meta, &object);
+    let fields = object_meta
+        .fields
+        .iter()
+        .filter_map(|name| {
+            let value = object.get(name)?;
+            let link = links.iter().find(|l| l.field == *name);
+            Some(serde_json::json!({ "name": name, "value": value, "link": link }))
+        })
+        .collect::<Vec<_>>();
+    context.insert("fields", &fields);
+    context.insert(
+        "backrefs",
+        &registry::reverse_links(store_rd, object_meta.table, object_id),
+    );
+    let history = history
+        .iter()
+        .map(|change| serde_json::json!({ "change": change, "fields": change.field_changes() }))
+        .collect::<Vec<_>>();
+    context.insert("history", &history);
+    context.insert(
+        "published_iteration",
+        &task.context.store.get_store_stat().iteration,
+    );
     context.insert("object_id", &object_id);
-    tera.render(tpl_name, &context)
+    Ok(templates::render(tpl_name, &context)?)
+}
+
+fn unknown_table(object_type: &str) -> HttpError {
+    HttpError::not_found(format!("unknown object type: {}", object_type).as_str())
 }
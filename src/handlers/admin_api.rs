@@ -0,0 +1,114 @@
+use crate::data::explain;
+use crate::data::objects::IdType;
+use crate::data::query;
+use crate::data::registry;
+use crate::handlers::admin;
+use crate::helpers;
+use crate::router::{HttpError, PathParams, QueryParams};
+use crate::task::AdminTask;
+use serde_json::{json, Value};
+
+pub type Handler = fn(&AdminTask, &PathParams) -> Result<Value, HttpError>;
+
+// json counterparts of the admin pages
+pub fn routes() -> Vec<(&'static str, Handler)> {
+    vec![
+        ("/admin/api/status", handle_status),
+        ("/admin/api/explain", handle_explain), // /admin/api/explain?pad_id=1&campaign_id=2
+        ("/admin/api/find", handle_find),       // ?id=1
+        ("/admin/api/store", handle_tables),
+        ("/admin/api/store/:table", handle_objects), // ?offset=0&limit=100&sort=-id&<field>=<value>
+        ("/admin/api/store/:table/:id", handle_object),
+        ("/admin/api/store/:table/:id/history", handle_history),
+    ]
+}
+
+pub fn handle(task: AdminTask, handler: Handler, params: &PathParams) {
+    log::debug!("start handing admin api task: {}", task.http_task.url());
+    match handler(&task, params) {
+        Ok(rsp) => task.http_task.respond_json(rsp.to_string().as_str()),
+        Err(e) => {
+            log::debug!("admin api task failed: {} {}", e.code, e.message);
+            task.http_task.respond_json_error(&e)
+        }
+    }
+}
+
+fn handle_status(task: &AdminTask, _: &PathParams) -> Result<Value, HttpError> {
+    to_json(&admin::status(task))
+}
+
+fn handle_explain(task: &AdminTask, _: &PathParams) -> Result<Value, HttpError> {
+    let (pad_id, campaign_id) = admin::explain_params(task)?;
+    let explain = explain::explain(
+        task.context.store.get_raw_data(),
+        pad_id,
+        campaign_id,
+        helpers::time::cur_ts() as i64,
+    );
+    to_json(&explain)
+}
+
+fn handle_tables(task: &AdminTask, _: &PathParams) -> Result<Value, HttpError> {
+    let store_rd = task.context.store.get_raw_data();
+    let tables = registry::object_types()
+        .iter()
+        .map(|t| json!({ "table": t.table, "count": (t.list)(store_rd).len() }))
+        .collect::<Vec<_>>();
+    Ok(json!({ "tables": tables }))
+}
+
+fn handle_objects(task: &AdminTask, params: &PathParams) -> Result<Value, HttpError> {
+    let object_meta = find_type(params)?;
+    let query = admin::object_query(task, &object_meta)?;
+    let result = query::run(&object_meta, task.context.store.get_raw_data(), &query);
+    Ok(json!({
+        "table": object_meta.table,
+        "total": result.total,
+        "offset": query.offset,
+        "limit": query.limit,
+        "ids": result.ids,
+    }))
+}
+
+fn handle_find(task: &AdminTask, _: &PathParams) -> Result<Value, HttpError> {
+    let id = QueryParams::from_url(task.http_task.url()).parse_or("id", 0)?;
+    let tables = registry::find_by_id(task.context.store.get_raw_data(), id);
+    Ok(json!({ "id": id, "tables": tables }))
+}
+
+fn handle_object(task: &AdminTask, params: &PathParams) -> Result<Value, HttpError> {
+    let object_meta = find_type(params)?;
+    let object_id = params.parse::<IdType>("id")?;
+    (object_meta.to_json)(task.context.store.get_raw_data(), object_id).ok_or_else(|| {
+        HttpError::not_found(format!("{} {} not found", object_meta.table, object_id).as_str())
+    })
+}
+
+// recent slave changes, newest first; kept for deleted objects too
+fn handle_history(task: &AdminTask, params: &PathParams) -> Result<Value, HttpError> {
+    let object_meta = find_type(params)?;
+    let object_id = params.parse::<IdType>("id")?;
+    let history = task
+        .context
+        .engine_stat
+        .history
+        .get((object_meta.table, object_id));
+    Ok(json!({
+        "table": object_meta.table,
+        "id": object_id,
+        "published_iteration": task.context.store.get_store_stat().iteration,
+        "history": history,
+    }))
+}
+
+fn find_type(params: &PathParams) -> Result<registry::ObjectType, HttpError> {
+    let table = params.get("table");
+    registry::find(table)
+        .ok_or_else(|| HttpError::not_found(format!("unknown object type: {}", table).as_str()))
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> Result<Value, HttpError> {
+    serde_json::to_value(value)
+        .map_err(|e| HttpError::internal(format!("fail to serialize: {}", e).as_str()))
+}
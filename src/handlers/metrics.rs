@@ -0,0 +1,51 @@
+use crate::metrics::MetricsWriter;
+use crate::task::AdminTask;
+
+pub fn handle(task: AdminTask) {
+    let mut writer = MetricsWriter::default();
+    task.context.engine_stat.write_metrics(&mut writer);
+
+    let store = task.context.store;
+    let name = "indexerd_store_objects";
+    writer.family(name, "gauge", "objects in the published store by table");
+    for (table, count) in store.get_raw_data().counts() {
+        writer.sample(name, &[("table", table)], count);
+    }
+    let name = "indexerd_store_iteration";
+    writer.family(name, "gauge", "iteration of the published store");
+    writer.sample(name, &[], store.get_store_stat().iteration);
+
+    let published = &store.get_store_stat().memory;
+    let write = task
+        .context
+        .engine_stat
+        .updater
+        .write_store_memory
+        .lock()
+        .unwrap()
+        .clone();
+    let stores = [("published", published), ("write", &write)];
+    let name = "indexerd_store_heap_bytes";
+    writer.family(name, "gauge", "estimated heap of store tables");
+    for (kind, memory) in stores.iter() {
+        for table in memory.tables.iter() {
+            let labels = [("store", *kind), ("table", table.table.as_str())];
+            writer.sample(name, &labels, table.heap_bytes);
+        }
+    }
+    let name = "indexerd_store_mapped_bytes";
+    writer.family(name, "gauge", "memory-mapped bytes of store tables");
+    for (kind, memory) in stores.iter() {
+        for table in memory.tables.iter() {
+            let labels = [("store", *kind), ("table", table.table.as_str())];
+            writer.sample(name, &labels, table.mapped_bytes);
+        }
+    }
+    let name = "indexerd_store_index_bytes";
+    writer.family(name, "gauge", "estimated heap of search structures");
+    for (kind, memory) in stores.iter() {
+        writer.sample(name, &[("store", *kind)], memory.index_bytes);
+    }
+
+    task.http_task.respond_metrics(writer.finish().as_str());
+}
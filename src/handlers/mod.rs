@@ -1,2 +1,4 @@
 pub mod admin;
+pub mod admin_api;
+pub mod metrics;
 pub mod search;
@@ -1,7 +1,22 @@
+use crate::data::explain;
+use crate::helpers;
+use crate::router::HttpError;
 use crate::task::SearchTask;
 
 pub fn handle(task: SearchTask) {
     match task.search_request {
+        Ok(search_req) if search_req.explain => {
+            let params = &search_req.search_params;
+            let explain = explain::explain(
+                task.context.store.get_raw_data(),
+                params.pad_id,
+                params.id,
+                helpers::time::cur_ts() as i64,
+            );
+            let response =
+                serde_json::to_string(&explain).unwrap_or("fail to serialize".to_string());
+            task.http_task.respond_json(response.as_str());
+        }
         Ok(search_req) => {
             let id = search_req.search_params.id;
             let mut response = format!("search_params = {:?}", search_req);
@@ -23,9 +38,9 @@ pub fn handle(task: SearchTask) {
             task.http_task.respond_html(response.as_str());
         }
         Err(e) => {
-            task.http_task
-                .respond_html(format!("malformed request: {}", e).as_str());
             log::warn!("malformed request: {}\n{}", e, e.backtrace());
+            let err = HttpError::bad_request(format!("malformed request: {}", e).as_str());
+            task.http_task.respond_error(&err);
         }
     }
 }
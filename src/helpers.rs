@@ -1,5 +1,6 @@
-use hwloc2::{CpuBindFlags, CpuSet, ObjectType, Topology};
+use hwloc2::{CpuBindFlags, CpuSet, ObjectType, Topology, TopologyObject};
 
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
@@ -57,6 +58,71 @@ pub fn bind_thread(core_num: usize) {
     );
 }
 
+// logical cores (PUs) as bind_thread numbers them, by physical core and by package
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct CpuTopology {
+    pub packages: Vec<Vec<Vec<usize>>>,
+}
+
+impl CpuTopology {
+    // single package without SMT
+    pub fn flat(count: usize) -> Self {
+        CpuTopology {
+            packages: vec![(0..count).map(|pu| vec![pu]).collect()],
+        }
+    }
+
+    pub fn pus_count(&self) -> usize {
+        self.packages.iter().flatten().map(Vec::len).sum()
+    }
+}
+
+pub fn cpu_topology() -> CpuTopology {
+    let topology = Topology::new().and_then(|topo| {
+        let pus = topo.objects_with_type(&ObjectType::PU).ok()?;
+        let mut packages = BTreeMap::<u32, BTreeMap<(bool, u32), Vec<usize>>>::new();
+        for (pu_num, pu) in pus.iter().enumerate() {
+            let package = ancestor_index(pu, |t| matches!(t, ObjectType::Package)).unwrap_or(0);
+            // a PU outside of any core counts as a core of its own
+            let core = match ancestor_index(pu, |t| matches!(t, ObjectType::Core)) {
+                Some(core) => (false, core),
+                None => (true, pu_num as u32),
+            };
+            packages
+                .entry(package)
+                .or_default()
+                .entry(core)
+                .or_default()
+                .push(pu_num);
+        }
+        Some(CpuTopology {
+            packages: packages
+                .into_values()
+                .map(|cores| cores.into_values().collect())
+                .collect(),
+        })
+    });
+    match topology {
+        Some(topology) if topology.pus_count() > 0 => topology,
+        _ => {
+            let count = thread::available_parallelism().map_or(1, |n| n.get());
+            log::warn!("fail to get cores from topology, fallback to {}", count);
+            CpuTopology::flat(count)
+        }
+    }
+}
+
+fn ancestor_index(object: &TopologyObject, is_kind: fn(&ObjectType) -> bool) -> Option<u32> {
+    let mut parent = object.parent();
+    while let Some(object) = parent {
+        if is_kind(&object.object_type()) {
+            return Some(object.logical_index());
+        }
+        parent = object.parent();
+    }
+    None
+}
+
 fn cpuset_for_core(topology: &Topology, idx: usize) -> Result<CpuSet, Box<dyn Error>> {
     let cores = (*topology).objects_with_type(&ObjectType::PU).unwrap();
     match cores.get(idx) {
@@ -14,10 +14,14 @@ use std::sync::Arc;
 
 mod handlers;
 mod helpers;
+mod metrics;
 mod proto;
+mod reload;
 mod request;
+mod router;
 mod server;
 mod task;
+mod templates;
 mod worker;
 
 use log::LevelFilter;
@@ -54,7 +58,9 @@ fn main() -> std::io::Result<()> {
     let wait_pair = Arc::new((Mutex::new(true), Condvar::new()));
 
     let server_conf = config::Server::from_file(conf_path.as_str())?;
-    let server = Server::new(&server_conf, wait_pair.clone())?;
+    templates::init(server_conf.service.templates_dir.as_deref())
+        .map_err(|e| std::io::Error::other(format!("fail to load templates: {:?}", e)))?;
+    let server = Server::new(&server_conf, conf_path.as_str(), wait_pair.clone())?;
     ctrlc::set_handler(move || {
         log::info!("received SIGINT");
         let (lock, cvar) = &*wait_pair;
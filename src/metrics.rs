@@ -0,0 +1,144 @@
+use std::fmt::{Display, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// seconds
+pub const LATENCY_BUCKETS: &[f64] = &[
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+];
+pub const SWAP_BUCKETS: &[f64] = &[0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0];
+
+// lock-free histogram, buckets are not cumulative until rendered
+pub struct Histogram {
+    bounds: &'static [f64],
+    buckets: Vec<AtomicU64>, // last one is +Inf
+    sum_us: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum_us: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        let pos = self
+            .bounds
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.buckets[pos].fetch_add(1, Ordering::Relaxed);
+        self.sum_us
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram::new(LATENCY_BUCKETS)
+    }
+}
+
+// Prometheus text exposition format 0.0.4
+#[derive(Default)]
+pub struct MetricsWriter {
+    out: String,
+}
+
+impl MetricsWriter {
+    pub fn family(&mut self, name: &str, kind: &str, help: &str) {
+        _ = writeln!(self.out, "# HELP {} {}", name, help);
+        _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+    }
+
+    pub fn sample<V: Display>(&mut self, name: &str, labels: &[(&str, &str)], value: V) {
+        _ = writeln!(
+            self.out,
+            "{}{} {}",
+            name,
+            format_labels(labels, None),
+            value
+        );
+    }
+
+    pub fn histogram(&mut self, name: &str, labels: &[(&str, &str)], histogram: &Histogram) {
+        let mut cumulative = 0;
+        for (pos, bucket) in histogram.buckets.iter().enumerate() {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let le = match histogram.bounds.get(pos) {
+                Some(bound) => bound.to_string(),
+                None => "+Inf".to_string(),
+            };
+            _ = writeln!(
+                self.out,
+                "{}_bucket{} {}",
+                name,
+                format_labels(labels, Some(&le)),
+                cumulative
+            );
+        }
+        let sum = histogram.sum_us.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        self.sample(format!("{}_sum", name).as_str(), labels, sum);
+        let count = histogram.count.load(Ordering::Relaxed);
+        self.sample(format!("{}_count", name).as_str(), labels, count);
+    }
+
+    pub fn finish(self) -> String {
+        self.out
+    }
+}
+
+fn format_labels(labels: &[(&str, &str)], le: Option<&str>) -> String {
+    let mut pairs = labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, escape_label(v)))
+        .collect::<Vec<_>>();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{}\"", le));
+    }
+    match pairs.is_empty() {
+        true => String::new(),
+        false => format!("{{{}}}", pairs.join(",")),
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_histogram_render() {
+        let histogram = Histogram::new(&[0.1, 1.0]);
+        histogram.observe(Duration::from_millis(50));
+        histogram.observe(Duration::from_millis(500));
+        histogram.observe(Duration::from_secs(2));
+
+        let mut writer = MetricsWriter::default();
+        writer.family("req_seconds", "histogram", "request latency");
+        writer.histogram("req_seconds", &[("route", "/a\"b")], &histogram);
+        let expected = "\
+# HELP req_seconds request latency
+# TYPE req_seconds histogram
+req_seconds_bucket{route=\"/a\\\"b\",le=\"0.1\"} 1
+req_seconds_bucket{route=\"/a\\\"b\",le=\"1\"} 2
+req_seconds_bucket{route=\"/a\\\"b\",le=\"+Inf\"} 3
+req_seconds_sum{route=\"/a\\\"b\"} 2.55
+req_seconds_count{route=\"/a\\\"b\"} 3
+";
+        assert_eq!(expected, writer.finish());
+    }
+}
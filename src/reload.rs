@@ -0,0 +1,70 @@
+use crate::config;
+use crate::router::HttpError;
+use crossbeam_channel::Sender;
+use serde_json::Value;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// things only the main thread may do: it owns the config and the updater
+pub enum ServerRequest {
+    ReloadConfig(ReloadRequest),
+    Action(ActionRequest),
+}
+
+// workers send a reply channel, the main thread performs the reload and answers through it
+pub struct ReloadRequest {
+    pub token: Option<String>, // same as for actions
+    pub reply: Sender<Result<config::ConfigDiff, HttpError>>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum AdminAction {
+    Swap,
+    FullReload,
+    Check { with_db: bool },
+}
+
+pub struct ActionRequest {
+    pub action: AdminAction,
+    pub token: Option<String>, // checked by the main thread against the current config
+    pub reply: Sender<Result<Value, HttpError>>, // action report
+}
+
+impl AdminAction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AdminAction::Swap => "swap",
+            AdminAction::FullReload => "full-reload",
+            AdminAction::Check { with_db: false } => "check",
+            AdminAction::Check { with_db: true } => "check-db",
+        }
+    }
+
+    // how long the requesting worker waits for the result
+    pub fn reply_timeout(&self) -> Duration {
+        match self {
+            AdminAction::Swap => Duration::from_secs(60),
+            AdminAction::FullReload | AdminAction::Check { with_db: true } => {
+                Duration::from_secs(600)
+            }
+            AdminAction::Check { with_db: false } => Duration::from_secs(60),
+        }
+    }
+}
+
+static SIGHUP_RECEIVED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sighup(_: libc::c_int) {
+    SIGHUP_RECEIVED.store(true, Ordering::Relaxed);
+}
+
+pub fn set_sighup_handler() {
+    let handler = on_sighup as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    if unsafe { libc::signal(libc::SIGHUP, handler) } == libc::SIG_ERR {
+        log::error!("fail to set SIGHUP handler");
+    }
+}
+
+pub fn take_sighup() -> bool {
+    SIGHUP_RECEIVED.swap(false, Ordering::Relaxed)
+}
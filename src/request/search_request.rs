@@ -14,6 +14,8 @@ pub struct SearchParams {
     pub name: String,
     pub id: i32,
     pub email: String,
+    #[serde(default)]
+    pub pad_id: i32,
 }
 
 #[derive(Debug, Eq, PartialEq, Display, EnumString)]
@@ -28,6 +30,7 @@ pub struct SearchRequest {
     #[allow(dead_code)]
     req_fmt: RequestFormat, // used only for debug logging
     pub search_params: SearchParams,
+    pub explain: bool,
 }
 
 impl SearchRequest {
@@ -44,15 +47,18 @@ impl SearchRequest {
             None => return Err(anyhow::anyhow!("search_params not found in url={}", url)),
         };
 
+        let explain = get_params.get("explain").is_some_and(|v| v == "1");
+
         let search_params = SearchRequest {
             req_fmt,
             search_params,
+            explain,
         };
 
         Ok(search_params)
     }
 
-    fn parse_get_params(url: &str) -> Result<HashMap<String, String>> {
+    pub fn parse_get_params(url: &str) -> Result<HashMap<String, String>> {
         let url = Url::parse((String::from("http://localhost:8088") + url).as_str())?;
         let mut params = HashMap::new();
 
@@ -85,6 +91,7 @@ impl SearchParams {
                     name: proto.name,
                     id: proto.id,
                     email: proto.email,
+                    pad_id: proto.pad_id,
                 })
             }
         }
@@ -123,12 +130,14 @@ mod test {
             name: "t2".into(),
             id: 16,
             email: "53".into(),
+            pad_id: 7,
         };
         let search_params = obj_to_base64(&sp);
 
         let expected = SearchRequest {
             req_fmt: RequestFormat::Json,
             search_params: sp,
+            explain: false,
         };
 
         for req_fmt in ["req_fmt=json", ""] {
@@ -146,12 +155,14 @@ mod test {
             name: "t3".into(),
             id: 17,
             email: "54".into(),
+            pad_id: 8,
         };
 
         let proto = crate::proto::search_params::SearchParams {
             name: sp.name.clone().into(),
             id: sp.id.clone().into(),
             email: sp.email.clone().into(),
+            pad_id: sp.pad_id,
         };
 
         let search_params = proto_to_base64(&proto);
@@ -159,6 +170,7 @@ mod test {
         let expected = SearchRequest {
             req_fmt: RequestFormat::Proto,
             search_params: sp,
+            explain: false,
         };
 
         let given = SearchRequest::from_url(
@@ -168,4 +180,16 @@ mod test {
 
         assert_eq!(expected, given);
     }
+
+    #[test]
+    fn test_search_request_from_url_explain() {
+        let search_params = obj_to_base64(&SearchParams::default());
+        for (explain, expected) in [("explain=1", true), ("explain=0", false), ("", false)] {
+            let given = SearchRequest::from_url(
+                format!("/search?{explain}&search_params={search_params}").as_str(),
+            )
+            .unwrap();
+            assert_eq!(expected, given.explain);
+        }
+    }
 }
@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::str::FromStr;
+use tiny_http::Method;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HttpError {
+    pub code: u16,
+    pub message: String,
+}
+
+impl HttpError {
+    pub fn new(code: u16, message: &str) -> Self {
+        HttpError {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn bad_request(message: &str) -> Self {
+        HttpError {
+            code: 400,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn not_found(message: &str) -> Self {
+        HttpError {
+            code: 404,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn internal(message: &str) -> Self {
+        HttpError {
+            code: 500,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<tera::Error> for HttpError {
+    fn from(e: tera::Error) -> Self {
+        HttpError::internal(format!("render error: {:?}", e).as_str())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PathParams {
+    route: &'static str,
+    values: HashMap<&'static str, String>,
+}
+
+impl PathParams {
+    // pattern of the matched route
+    pub fn route(&self) -> &'static str {
+        self.route
+    }
+
+    pub fn get(&self, name: &str) -> &str {
+        self.values.get(name).map(|v| v.as_str()).unwrap_or("")
+    }
+
+    pub fn parse<T: FromStr>(&self, name: &str) -> Result<T, HttpError>
+    where
+        T::Err: Debug,
+    {
+        let value = self.get(name);
+        value.parse::<T>().map_err(|e| {
+            HttpError::bad_request(format!("bad {}='{}': {:?}", name, value, e).as_str())
+        })
+    }
+}
+
+// query string of a request, '?a=1&b=2'
+#[derive(Debug, Default)]
+pub struct QueryParams {
+    values: HashMap<String, String>,
+}
+
+impl QueryParams {
+    pub fn from_url(url: &str) -> Self {
+        let query = url.split_once('?').map(|(_, q)| q).unwrap_or("");
+        let values = url::form_urlencoded::parse(query.as_bytes())
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect();
+        QueryParams { values }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(|v| v.as_str())
+    }
+
+    // non-empty values only, forms submit empty inputs too
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values
+            .iter()
+            .filter(|(_, v)| !v.is_empty())
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    // missing and empty values fall back to default
+    pub fn parse_or<T: FromStr>(&self, name: &str, default: T) -> Result<T, HttpError>
+    where
+        T::Err: Debug,
+    {
+        match self.values.get(name) {
+            Some(value) if !value.is_empty() => value.parse::<T>().map_err(|e| {
+                HttpError::bad_request(format!("bad {}='{}': {:?}", name, value, e).as_str())
+            }),
+            _ => Ok(default),
+        }
+    }
+}
+
+enum Segment {
+    Static(&'static str),
+    Param(&'static str), // written as ':name' in a pattern
+}
+
+struct Route<H> {
+    pattern: &'static str,
+    methods: Vec<Method>,
+    segments: Vec<Segment>,
+    handler: H,
+}
+
+pub enum RouteMatch<'a, H> {
+    Found(&'a H, PathParams),
+    MethodNotAllowed(Vec<Method>),
+    NotFound,
+}
+
+pub struct Router<H> {
+    routes: Vec<Route<H>>,
+}
+
+// path without query string and trailing slash
+pub fn url_path(url: &str) -> &str {
+    let path = url.split('?').next().unwrap_or("");
+    match path.len() > 1 {
+        true => path.trim_end_matches('/'),
+        false => path,
+    }
+}
+
+impl<H> Router<H> {
+    pub fn new() -> Self {
+        Router { routes: Vec::new() }
+    }
+
+    pub fn add(mut self, methods: &[Method], pattern: &'static str, handler: H) -> Self {
+        let segments = pattern
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(|s| match s.strip_prefix(':') {
+                Some(name) => Segment::Param(name),
+                None => Segment::Static(s),
+            })
+            .collect();
+        self.routes.push(Route {
+            pattern,
+            methods: methods.to_vec(),
+            segments,
+            handler,
+        });
+        self
+    }
+
+    pub fn find(&self, method: &Method, url: &str) -> RouteMatch<'_, H> {
+        let parts = url_path(url)
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>();
+
+        let mut allowed = Vec::new();
+        for route in self.routes.iter() {
+            let params = match Self::match_segments(route, &parts) {
+                Some(params) => params,
+                None => continue,
+            };
+            if route.methods.contains(method) {
+                return RouteMatch::Found(&route.handler, params);
+            }
+            allowed.extend(route.methods.iter().cloned());
+        }
+        match allowed.is_empty() {
+            true => RouteMatch::NotFound,
+            false => RouteMatch::MethodNotAllowed(allowed),
+        }
+    }
+
+    pub fn patterns(&self) -> Vec<&'static str> {
+        self.routes.iter().map(|route| route.pattern).collect()
+    }
+
+    fn match_segments(route: &Route<H>, parts: &[&str]) -> Option<PathParams> {
+        if route.segments.len() != parts.len() {
+            return None;
+        }
+        let mut params = PathParams {
+            route: route.pattern,
+            ..Default::default()
+        };
+        for (segment, part) in route.segments.iter().zip(parts.iter()) {
+            match segment {
+                Segment::Static(s) if s == part => {}
+                Segment::Static(_) => return None,
+                Segment::Param(name) => {
+                    params.values.insert(name, part.to_string());
+                }
+            }
+        }
+        Some(params)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn router() -> Router<i32> {
+        Router::new()
+            .add(&[Method::Get], "/admin", 1)
+            .add(&[Method::Get], "/admin/store/:table", 2)
+            .add(&[Method::Get], "/admin/store/:table/:id", 3)
+            .add(&[Method::Post], "/admin/actions/swap", 4)
+    }
+
+    #[test]
+    fn test_router_find() {
+        let router = router();
+        for (url, expected) in [
+            ("/admin", 1),
+            ("/admin/", 1),
+            ("/admin/store/campaign?limit=1", 2),
+            ("/admin/store/campaign/12/", 3),
+        ] {
+            match router.find(&Method::Get, url) {
+                RouteMatch::Found(given, _) => assert_eq!(expected, *given, "url={}", url),
+                _ => panic!("route not found for url={}", url),
+            }
+        }
+        assert!(matches!(
+            router.find(&Method::Get, "/admin/unknown"),
+            RouteMatch::NotFound
+        ));
+        assert!(matches!(
+            router.find(&Method::Get, "/admin/actions/swap"),
+            RouteMatch::MethodNotAllowed(allowed) if allowed == vec![Method::Post]
+        ));
+    }
+
+    #[test]
+    fn test_router_path_params() {
+        let router = router();
+        let params = match router.find(&Method::Get, "/admin/store/campaign/abc") {
+            RouteMatch::Found(_, params) => params,
+            _ => panic!("route not found"),
+        };
+        assert_eq!("/admin/store/:table/:id", params.route());
+        assert_eq!("campaign", params.get("table"));
+        assert_eq!(400, params.parse::<i32>("id").unwrap_err().code);
+        assert_eq!("", params.get("unknown"));
+    }
+
+    #[test]
+    fn test_query_params() {
+        let query = QueryParams::from_url("/admin/api/store/pad?offset=20&limit=&flag&bad=x");
+        assert_eq!(20, query.parse_or("offset", 0).unwrap());
+        assert_eq!(100, query.parse_or("limit", 100).unwrap());
+        assert_eq!(7, query.parse_or("missing", 7).unwrap());
+        assert_eq!(400, query.parse_or("bad", 0).unwrap_err().code);
+        let query = QueryParams::from_url("/admin/store/pad?name=big+red%21");
+        assert_eq!(Some("big red!"), query.get("name"));
+    }
+}
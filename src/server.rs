@@ -2,52 +2,111 @@ extern crate crossbeam_channel;
 extern crate hwloc2;
 extern crate libc;
 extern crate tiny_http;
+use crate::reload::{self, ActionRequest, AdminAction, ServerRequest};
+use crate::router::HttpError;
 use crate::task::HttpTask;
 use crate::{config, helpers};
-use crossbeam_channel::Sender;
+use crossbeam_channel::{Receiver, Sender, TrySendError};
+use serde_json::{json, Value};
 
-use std::io::Error;
+use std::io::{Error, ErrorKind};
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
 
-use crate::data::updater::{Updater, UpdaterPtr};
-use crate::engine::Engine;
+use crate::data::updater::{self, Updater, UpdaterPtr};
+use crate::engine::{Engine, EngineStat};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Condvar, Mutex, RwLock};
 use std::time::Duration;
 use std::{thread, thread::JoinHandle};
 
 pub struct Server {
-    #[allow(dead_code)]
     conf: config::Server,
-    http_srv: JoinHandle<()>,
+    conf_path: String,
+    server_queue: Receiver<ServerRequest>,
+    http_srvs: Vec<JoinHandle<()>>,
     engine: Arc<RwLock<Engine>>,
     updater: UpdaterPtr,
+    actions: (Sender<ActionJob>, JoinHandle<()>),
     stop_flag: Arc<AtomicBool>,
     wait_pair: Arc<(Mutex<bool>, Condvar)>,
 }
 
+// an authorized action and where its report goes
+type ActionJob = (AdminAction, Sender<Result<Value, HttpError>>);
+
 impl Server {
     pub fn new(
         conf: &config::Server,
+        conf_path: &str,
         wait_pair: Arc<(Mutex<bool>, Condvar)>,
     ) -> Result<Server, Error> {
-        let (send_queue, rcv_queue) = crossbeam_channel::bounded(1000);
+        let topology = helpers::cpu_topology();
+        let cores = conf
+            .engine
+            .core_assignment(&topology)
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("bad cpu_layout: {}", e)))?;
+        log::info!(
+            "cpu topology: {:?}, assignment: {:?}",
+            topology.packages,
+            cores
+        );
+
+        let (send_queue, rcv_queue) = crossbeam_channel::bounded(conf.service.task_queue_size);
+        let admin_conf = conf.service.admin.as_ref().map(|a| a.as_service());
+        let (admin_send_queue, admin_rcv_queue) = match admin_conf.as_ref() {
+            Some(admin) => {
+                let (snd, rcv) = crossbeam_channel::bounded(admin.task_queue_size);
+                (Some(snd), Some(rcv))
+            }
+            None => (None, None),
+        };
+        let (server_queue_snd, server_queue_rcv) = crossbeam_channel::bounded(10);
         let stop_flag = Arc::new(AtomicBool::new(false));
 
-        let http_srv = run_http_listener(
-            stop_flag.clone(),
-            conf.service.listening_port,
+        let engine = Arc::new(RwLock::new(Engine::new(
+            &conf.engine,
+            &cores,
+            rcv_queue,
+            admin_rcv_queue,
+            server_queue_snd,
+        )));
+        let engine_stat = engine.read().unwrap().stat();
+
+        let mut http_srvs = run_http_listeners(
             "http_srv",
+            "search",
+            stop_flag.clone(),
+            &conf.service,
+            &cores.http,
             &send_queue,
+            engine_stat.clone(),
         )?;
+        if let (Some(admin_conf), Some(admin_send_queue)) = (admin_conf, admin_send_queue) {
+            http_srvs.extend(run_http_listeners(
+                "admin_srv",
+                "admin",
+                stop_flag.clone(),
+                &admin_conf,
+                &[cores.cron],
+                &admin_send_queue,
+                engine_stat,
+            )?);
+        }
+        let updater = Updater::new(&conf.updater, &cores, engine.clone()).unwrap();
+
+        let actions = run_actions(updater.clone());
 
-        let engine = Arc::new(RwLock::new(Engine::new(&conf.engine, rcv_queue)));
-        let updater = Updater::new(&conf.updater, engine.clone()).unwrap();
+        reload::set_sighup_handler();
 
         let server = Self {
             conf: conf.clone(),
-            http_srv,
+            conf_path: conf_path.to_string(),
+            server_queue: server_queue_rcv,
+            http_srvs,
             engine,
             updater,
+            actions,
             stop_flag,
             wait_pair,
         };
@@ -55,16 +114,46 @@ impl Server {
         Ok(server)
     }
 
-    pub fn wait_stop(self) -> std::io::Result<()> {
-        let (lock, cvar) = &*self.wait_pair;
-        let mut working = lock.lock().unwrap();
-        while *working {
-            working = cvar.wait(working).unwrap();
+    pub fn wait_stop(mut self) -> std::io::Result<()> {
+        let wait_pair = self.wait_pair.clone();
+        let (lock, cvar) = &*wait_pair;
+        loop {
+            {
+                let working = lock.lock().unwrap();
+                if !*working {
+                    break;
+                }
+                let _ = cvar
+                    .wait_timeout(working, Duration::from_millis(100))
+                    .unwrap();
+            }
+            if reload::take_sighup() {
+                log::info!("received SIGHUP");
+                let _ = self.reload_config();
+            }
+            while let Ok(request) = self.server_queue.try_recv() {
+                match request {
+                    ServerRequest::ReloadConfig(request) => {
+                        let result = authorize(&self.conf.admin_actions, request.token.as_deref())
+                            .and_then(|_| {
+                                self.reload_config()
+                                    .map_err(|e| HttpError::bad_request(e.as_str()))
+                            });
+                        let _ = request.reply.send(result);
+                    }
+                    ServerRequest::Action(request) => self.dispatch_action(request),
+                }
+            }
         }
 
         log::info!("stop signal received, shutting down...");
-        let mut updater_locked = self.updater.write().unwrap();
-        updater_locked.stop();
+        let (actions, actions_thread) = self.actions;
+        drop(actions);
+        match actions_thread.is_finished() {
+            true => actions_thread.join().expect("fail join actions thread"),
+            false => log::warn!("admin action is still running, not waiting for it"),
+        }
+        updater::stop(&self.updater);
 
         log::info!("stopping services...");
         self.stop_flag.store(true, Ordering::Release);
@@ -72,51 +161,211 @@ impl Server {
         let mut engine_locked = self.engine.write().unwrap();
         engine_locked.stop();
 
-        self.http_srv.join().expect("fail join admin_srv thread");
-        log::info!("admin service stopped");
+        for th in self.http_srvs.into_iter() {
+            th.join().expect("fail join http_srv thread");
+        }
+        log::info!("http service stopped");
 
         log::info!("app stopped");
         Ok(())
     }
+
+    // checked here since the config is owned by the main thread, run by the actions one
+    fn dispatch_action(&self, request: ActionRequest) {
+        match authorize(&self.conf.admin_actions, request.token.as_deref()) {
+            Ok(_) => dispatch_action(&self.actions.0, (request.action, request.reply)),
+            Err(e) => {
+                let _ = request.reply.send(Err(e));
+            }
+        }
+    }
+
+    // re-reads config from disk and applies options which don't require restart
+    fn reload_config(&mut self) -> Result<config::ConfigDiff, String> {
+        let new_conf = match config::Server::from_file(self.conf_path.as_str()) {
+            Ok(c) => c,
+            Err(e) => {
+                log::error!("fail to reload config from {}: {}", self.conf_path, e);
+                return Err(format!("fail to read config: {}", e));
+            }
+        };
+        let diff = self.conf.diff(&new_conf);
+
+        if self.conf.engine.worker != new_conf.engine.worker {
+            self.conf.engine.worker = new_conf.engine.worker;
+            self.engine
+                .write()
+                .unwrap()
+                .update_config(self.conf.engine.clone());
+        }
+        self.conf.admin_actions = new_conf.admin_actions;
+        let history_changed = self.conf.updater.history != new_conf.updater.history;
+        if self.conf.updater.take_live(&new_conf.updater) {
+            let mut updater = self.updater.write().unwrap();
+            updater.conf.take_live(&new_conf.updater);
+            if history_changed {
+                updater
+                    .engine_stat()
+                    .history
+                    .configure(&new_conf.updater.history);
+            }
+        }
+
+        log::info!(
+            "config reloaded: applied={:?}, restart_required={:?}",
+            diff.applied,
+            diff.restart_required
+        );
+        Ok(diff)
+    }
+}
+
+// one action at a time, the actions thread takes a job only when idle
+fn dispatch_action(actions: &Sender<ActionJob>, job: ActionJob) {
+    if let Err(e) = actions.try_send(job) {
+        let busy = matches!(e, TrySendError::Full(_));
+        let (action, reply) = e.into_inner();
+        let err = match busy {
+            true => HttpError::new(409, "another action is running, try again later"),
+            false => HttpError::new(503, "shutting down"),
+        };
+        log::warn!("admin action {} refused: {}", action.name(), err.message);
+        let _ = reply.send(Err(err));
+    }
+}
+
+// actions take minutes, the main thread stays free for reloads, signals and shutdown
+fn run_actions(updater: UpdaterPtr) -> (Sender<ActionJob>, JoinHandle<()>) {
+    let (actions, jobs) = crossbeam_channel::bounded::<ActionJob>(0);
+    let handle = thread::Builder::new()
+        .name(String::from("actions"))
+        .spawn(move || {
+            for (action, reply) in jobs.iter() {
+                let _ = reply.send(run_action(&updater, action));
+            }
+        })
+        .expect("fail to run actions thread");
+    (actions, handle)
+}
+
+fn run_action(updater: &UpdaterPtr, action: AdminAction) -> Result<Value, HttpError> {
+    log::info!("running admin action: {}", action.name());
+    let result = match action {
+        AdminAction::Swap => updater::swap_stores(updater).map(|r| json!(r)),
+        AdminAction::FullReload => updater::full_reload(updater).map(|r| json!(r)),
+        AdminAction::Check { with_db } => {
+            updater::check_consistency(updater, with_db).map(|r| json!(r))
+        }
+    };
+    match result.as_ref() {
+        Ok(report) => log::info!("admin action {} done: {:?}", action.name(), report),
+        Err(e) => log::error!("admin action {} failed: {}", action.name(), e),
+    }
+    result.map_err(|e| HttpError::new(409, e.as_str()))
+}
+
+fn authorize(conf: &config::AdminActions, given: Option<&str>) -> Result<(), HttpError> {
+    let token = match conf.token.as_ref() {
+        Some(token) if !token.is_empty() => token,
+        _ => return Err(HttpError::new(403, "admin actions are disabled")),
+    };
+    match given.is_some_and(|t| token_eq(t, token)) {
+        true => Ok(()),
+        false => Err(HttpError::new(401, "bad or missing token")),
+    }
+}
+
+// compares in time independent of where the first difference is
+fn token_eq(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+fn bind_addr(host: &str, port: u16) -> String {
+    match host.contains(':') {
+        true => format!("[{}]:{}", host.trim_matches(|c| c == '[' || c == ']'), port),
+        false => format!("{}:{}", host, port),
+    }
 }
 
-fn run_http_listener(
+// binds every configured address first, so a bad config fails the start instead of a thread
+fn run_http_listeners(
+    name: &str,
+    queue: &'static str, // for the depth stat
     shutdown: Arc<AtomicBool>,
-    port: u16,
-    th_name: &str,
+    conf: &config::Service,
+    cores: &[usize],
     send_queue: &Sender<HttpTask>,
-) -> Result<JoinHandle<()>, Error> {
-    let bind_addr = format!("127.0.0.1:{}", port);
-    log::info!(
-        "thread {} (bind: http://{}) starting...",
-        th_name,
-        bind_addr
-    );
+    stat: Arc<EngineStat>,
+) -> Result<Vec<JoinHandle<()>>, Error> {
+    let mut listeners = Vec::new();
+    for host in conf.bind_hosts.iter() {
+        let addr = bind_addr(host, conf.listening_port);
+        let service = tiny_http::Server::http(addr.as_str())
+            .map_err(|e| Error::other(format!("fail to bind {}: {}", addr, e)))?;
+        listeners.push((format!("http://{}", addr), Arc::new(service)));
+    }
+    if let Some(path) = conf.unix_socket.as_ref() {
+        remove_stale_socket(path)?;
+        let service = tiny_http::Server::http_unix(Path::new(path))
+            .map_err(|e| Error::other(format!("fail to bind {}: {}", path, e)))?;
+        listeners.push((format!("unix:{}", path), Arc::new(service)));
+    }
 
-    let send_queue = send_queue.clone();
-    let th_builder = thread::Builder::new().name(th_name.to_string());
-    let th = th_builder.spawn(move || {
-        helpers::bind_thread(0);
-        let service = match tiny_http::Server::http(bind_addr.as_str()) {
-            Ok(s) => s,
-            _ => panic!("Fail to start service for bind={}", bind_addr),
-        };
-        service_loop(service, send_queue, shutdown);
-    })?;
+    let mut threads = Vec::new();
+    for (listener_num, (bind, service)) in listeners.into_iter().enumerate() {
+        for acceptor_num in 0..conf.acceptor_threads.max(1) {
+            let th_name = format!("{}_{}_{}", name, listener_num, acceptor_num);
+            let core = cores[threads.len() % cores.len()];
+            log::info!("thread {} (bind: {}) starting...", th_name, bind);
 
-    log::info!("thread {} started", th_name);
-    Ok(th)
+            let (service, send_queue, conf, stat, shutdown) = (
+                service.clone(),
+                send_queue.clone(),
+                conf.clone(),
+                stat.clone(),
+                shutdown.clone(),
+            );
+            let th = thread::Builder::new()
+                .name(th_name.clone())
+                .spawn(move || {
+                    helpers::bind_thread(core);
+                    service_loop(&service, (queue, send_queue), &conf, &stat, shutdown);
+                })?;
+            log::info!("thread {} started", th_name);
+            threads.push(th);
+        }
+    }
+    Ok(threads)
+}
+
+// a socket left by a previous run makes bind fail; anything else at the path is a config error
+fn remove_stale_socket(path: &str) -> Result<(), Error> {
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path),
+        Ok(_) => Err(Error::new(
+            ErrorKind::AlreadyExists,
+            format!("unix_socket path {} exists and is not a socket", path),
+        )),
+        Err(_) => Ok(()),
+    }
 }
 
 fn service_loop(
-    service: tiny_http::Server,
-    engine_queue: Sender<HttpTask>,
+    service: &tiny_http::Server,
+    engine_queue: (&str, Sender<HttpTask>),
+    conf: &config::Service,
+    stat: &EngineStat,
     shutdown: Arc<AtomicBool>,
 ) {
     let mut stop_checker = helpers::StopChecker::new(shutdown);
     while !stop_checker.is_time() {
         if let Ok(Some(req)) = service.recv_timeout(Duration::from_millis(50)) {
-            handle_connection(req, &engine_queue);
+            handle_connection(req, (engine_queue.0, &engine_queue.1), conf, stat);
         }
     }
     log::info!(
@@ -125,7 +374,12 @@ fn service_loop(
     );
 }
 
-fn handle_connection(req: tiny_http::Request, queue: &Sender<HttpTask>) {
+fn handle_connection(
+    req: tiny_http::Request,
+    (queue_name, queue): (&str, &Sender<HttpTask>),
+    conf: &config::Service,
+    stat: &EngineStat,
+) {
     log::trace!(
         "received request! method: {:?}, url: {:?}, headers: {:?}",
         req.method(),
@@ -133,9 +387,150 @@ fn handle_connection(req: tiny_http::Request, queue: &Sender<HttpTask>) {
         req.headers()
     );
 
-    let task = HttpTask::new(req);
+    let timeout = match conf.request_timeout_ms {
+        0 => None,
+        ms => Some(Duration::from_millis(ms)),
+    };
+    let task = HttpTask::new(req, timeout);
+
+    // never block here: a stalled acceptor is worse than a rejected request
+    let depth = stat.task_queue_depth(queue_name);
+    if let Some(depth) = depth {
+        depth.push();
+    }
+    let rejected = match queue.try_send(task) {
+        Ok(_) => return,
+        Err(rejected) => rejected,
+    };
+    if let Some(depth) = depth {
+        depth.pop();
+    }
+    match rejected {
+        TrySendError::Full(task) => {
+            stat.rejected_requests.fetch_add(1, Ordering::Relaxed);
+            task.respond_unavailable("overloaded", Some(conf.retry_after_sec));
+        }
+        TrySendError::Disconnected(task) => {
+            log::warn!("Fail to add request to queue: disconnected");
+            task.respond_unavailable("shutting down", None);
+        }
+    }
+}
 
-    if let Err(e) = queue.send(task) {
-        log::warn!("Fail to add request to queue with err={}", e)
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::handlers::admin;
+    use crate::task::test::{http_request, http_request_with};
+
+    #[test]
+    fn test_full_queue_is_rejected() {
+        let conf = config::Service {
+            retry_after_sec: 7,
+            ..Default::default()
+        };
+        let stat = EngineStat {
+            task_queues: vec![("search", Default::default())],
+            ..Default::default()
+        };
+        let (queue, queued) = crossbeam_channel::bounded(1);
+
+        let (_server, req, _) = http_request("/search?id=1");
+        handle_connection(req, ("search", &queue), &conf, &stat);
+        assert_eq!(1, queued.len());
+
+        let (_server, req, client) = http_request("/search?id=2");
+        handle_connection(req, ("search", &queue), &conf, &stat);
+        let response = client.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 503"));
+        assert!(response.contains("Retry-After: 7"));
+        assert_eq!(1, stat.rejected_requests.load(Ordering::Relaxed));
+        assert_eq!(1, queued.len());
+        assert_eq!(1, stat.task_queue_depth("search").unwrap().get());
+
+        // nobody left to take tasks
+        drop(queued);
+        let (_server, req, client) = http_request("/search?id=3");
+        handle_connection(req, ("search", &queue), &conf, &stat);
+        assert!(client.join().unwrap().ends_with("shutting down"));
+    }
+
+    #[test]
+    fn test_busy_action_is_refused() {
+        let (actions, jobs) = crossbeam_channel::bounded::<ActionJob>(0);
+        let (reply, result) = crossbeam_channel::bounded(1);
+        dispatch_action(&actions, (AdminAction::Swap, reply.clone()));
+        assert_eq!(409, result.recv().unwrap().unwrap_err().code);
+
+        // taken once the actions thread waits for it
+        let taker = thread::spawn(move || jobs.recv().unwrap().0);
+        let taken = (0..500).any(|_| {
+            dispatch_action(&actions, (AdminAction::Swap, reply.clone()));
+            let refused = result.try_recv().is_ok();
+            if refused {
+                thread::sleep(Duration::from_millis(1));
+            }
+            !refused
+        });
+        assert!(taken);
+        assert_eq!(AdminAction::Swap, taker.join().unwrap());
+    }
+
+    #[test]
+    fn test_token_eq() {
+        assert!(token_eq("secret", "secret"));
+        assert!(!token_eq("secreT", "secret"));
+        assert!(!token_eq("secret2", "secret"));
+        assert!(!token_eq("", "secret"));
+    }
+
+    #[test]
+    fn test_unauthorized_action_is_rejected() {
+        let disabled = authorize(&Default::default(), Some("")).unwrap_err();
+        assert_eq!(403, disabled.code);
+        let empty = config::AdminActions {
+            token: Some(String::new()),
+        };
+        assert_eq!(403, authorize(&empty, Some("")).unwrap_err().code);
+
+        // answers in place of the main thread, without running the action
+        let conf = config::AdminActions {
+            token: Some("secret".to_string()),
+        };
+        let (server_queue, requests) = crossbeam_channel::unbounded();
+        let main = thread::spawn(move || {
+            for request in requests.iter() {
+                match request {
+                    ServerRequest::Action(request) => {
+                        let result =
+                            authorize(&conf, request.token.as_deref()).map(|_| json!("done"));
+                        request.reply.send(result).unwrap();
+                    }
+                    ServerRequest::ReloadConfig(request) => {
+                        let result = authorize(&conf, request.token.as_deref())
+                            .map(|_| config::ConfigDiff::default());
+                        request.reply.send(result).unwrap();
+                    }
+                }
+            }
+        });
+        for (headers, status) in [
+            ("", "HTTP/1.1 401"),
+            ("Authorization: secret\r\n", "HTTP/1.1 401"),
+            ("Authorization: Bearer wrong\r\n", "HTTP/1.1 401"),
+            ("Authorization: Bearer secret\r\n", "HTTP/1.1 200"),
+        ] {
+            let (_server, req, client) = http_request_with("POST", "/admin/actions/swap", headers);
+            admin::handle_action(HttpTask::new(req, None), AdminAction::Swap, &server_queue);
+            let response = client.join().unwrap();
+            assert!(response.starts_with(status), "{}: {}", headers, response);
+
+            let (_server, req, client) = http_request_with("POST", "/admin/config/reload", headers);
+            admin::handle_config_reload(HttpTask::new(req, None), &server_queue);
+            let response = client.join().unwrap();
+            assert!(response.starts_with(status), "{}: {}", headers, response);
+        }
+        drop(server_queue);
+        main.join().unwrap();
     }
 }
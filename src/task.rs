@@ -2,16 +2,21 @@ extern crate tiny_http;
 
 use crate::config;
 use crate::data::store::Store;
+use crate::engine::EngineStat;
+use crate::router::HttpError;
 use anyhow::Result;
-use tiny_http::Header;
+use std::time::{Duration, Instant};
+use tiny_http::{Header, Method};
 
 pub struct HttpTask {
     raw_req: tiny_http::Request,
+    deadline: Option<Instant>,
 }
 
 pub struct TaskContext<'a> {
     pub store: &'a Store,
     pub config: &'a config::Worker,
+    pub engine_stat: &'a EngineStat,
 }
 
 pub struct AdminTask<'a> {
@@ -26,8 +31,15 @@ pub struct SearchTask<'a> {
 }
 
 impl HttpTask {
-    pub fn new(req: tiny_http::Request) -> Self {
-        HttpTask { raw_req: req }
+    pub fn new(req: tiny_http::Request, timeout: Option<Duration>) -> Self {
+        HttpTask {
+            raw_req: req,
+            deadline: timeout.map(|t| Instant::now() + t),
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.deadline.is_some_and(|d| Instant::now() > d)
     }
 
     pub fn respond_html(self, body: &str) {
@@ -36,6 +48,57 @@ impl HttpTask {
         _ = self.raw_req.respond(resp);
     }
 
+    pub fn respond_json(self, body: &str) {
+        let mut resp = tiny_http::Response::from_string(body);
+        resp.add_header(
+            Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap(),
+        );
+        _ = self.raw_req.respond(resp);
+    }
+
+    pub fn respond_metrics(self, body: &str) {
+        let mut resp = tiny_http::Response::from_string(body);
+        resp.add_header(
+            Header::from_bytes(&b"Content-Type"[..], &b"text/plain; version=0.0.4"[..]).unwrap(),
+        );
+        _ = self.raw_req.respond(resp);
+    }
+
+    pub fn respond_unavailable(self, body: &str, retry_after_sec: Option<u64>) {
+        let mut resp = tiny_http::Response::from_string(body).with_status_code(503);
+        if let Some(retry_after) = retry_after_sec {
+            let value = retry_after.to_string();
+            resp.add_header(Header::from_bytes(&b"Retry-After"[..], value.as_bytes()).unwrap());
+        }
+        _ = self.raw_req.respond(resp);
+    }
+
+    pub fn respond_error(self, err: &HttpError) {
+        let resp =
+            tiny_http::Response::from_string(err.message.as_str()).with_status_code(err.code);
+        _ = self.raw_req.respond(resp);
+    }
+
+    pub fn respond_json_error(self, err: &HttpError) {
+        let body = serde_json::json!({ "error": err.message }).to_string();
+        let mut resp = tiny_http::Response::from_string(body).with_status_code(err.code);
+        resp.add_header(
+            Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap(),
+        );
+        _ = self.raw_req.respond(resp);
+    }
+
+    pub fn respond_method_not_allowed(self, allowed: &[Method]) {
+        let allowed = allowed
+            .iter()
+            .map(|m| m.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut resp = tiny_http::Response::from_string("method not allowed").with_status_code(405);
+        resp.add_header(Header::from_bytes(&b"Allow"[..], allowed.as_bytes()).unwrap());
+        _ = self.raw_req.respond(resp);
+    }
+
     pub fn _respond_bin(self, body: &str) {
         let mut resp = tiny_http::Response::from_string(body);
         resp.add_header(Header::from_bytes(&b"Content-Type"[..], &b"binary"[..]).unwrap());
@@ -43,28 +106,117 @@ impl HttpTask {
     }
 
     pub fn url(&self) -> &str {
-        return self.raw_req.url();
+        self.raw_req.url()
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.raw_req
+            .headers()
+            .iter()
+            .find(|h| h.field.as_str().as_str().eq_ignore_ascii_case(name))
+            .map(|h| h.value.as_str())
+    }
+
+    pub fn method(&self) -> &Method {
+        self.raw_req.method()
     }
 }
 
 impl<'a> AdminTask<'a> {
-    pub fn new(http_task: HttpTask, store: &'a Store, config: &'a config::Worker) -> Self {
+    pub fn new(
+        http_task: HttpTask,
+        store: &'a Store,
+        config: &'a config::Worker,
+        engine_stat: &'a EngineStat,
+    ) -> Self {
         AdminTask {
             http_task,
-            context: TaskContext { store, config },
+            context: TaskContext {
+                store,
+                config,
+                engine_stat,
+            },
         }
     }
 }
 
 impl<'a> SearchTask<'a> {
-    pub fn new(http_task: HttpTask, store: &'a Store, config: &'a config::Worker) -> Self {
+    pub fn new(
+        http_task: HttpTask,
+        store: &'a Store,
+        config: &'a config::Worker,
+        engine_stat: &'a EngineStat,
+    ) -> Self {
         let search_request =
             crate::request::search_request::SearchRequest::from_url(http_task.url());
 
         SearchTask {
             http_task,
-            context: TaskContext { store, config },
+            context: TaskContext {
+                store,
+                config,
+                engine_stat,
+            },
             search_request,
         }
     }
 }
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::thread::{self, JoinHandle};
+
+    // a real request over loopback; the client returns the raw response once the task
+    // responds, the server has to outlive the request
+    pub fn http_request(path: &str) -> (tiny_http::Server, tiny_http::Request, JoinHandle<String>) {
+        http_request_with("GET", path, "")
+    }
+
+    // headers are raw lines, each ending with \r\n
+    pub fn http_request_with(
+        method: &str,
+        path: &str,
+        headers: &str,
+    ) -> (tiny_http::Server, tiny_http::Request, JoinHandle<String>) {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let addr = server.server_addr().to_ip().unwrap();
+        let head = format!(
+            "{} {} HTTP/1.1\r\nHost: localhost\r\n{}",
+            method, path, headers
+        );
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(
+                stream,
+                "{}Content-Length: 0\r\nConnection: close\r\n\r\n",
+                head
+            )
+            .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+        let req = server.recv().unwrap();
+        (server, req, client)
+    }
+
+    #[test]
+    fn test_task_deadline() {
+        let (_server, req, _) = http_request("/search");
+        assert!(!HttpTask::new(req, None).is_expired());
+
+        let (_server, req, _) = http_request("/search");
+        let task = HttpTask::new(req, Some(Duration::from_millis(1)));
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(task.is_expired());
+
+        let (_server, req, client) = http_request("/search");
+        HttpTask::new(req, None).respond_unavailable("overloaded", Some(3));
+        let response = client.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 503"));
+        assert!(response.contains("Retry-After: 3"));
+    }
+}
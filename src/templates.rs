@@ -0,0 +1,141 @@
+use arc_swap::ArcSwap;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+use tera::{Context, Tera};
+
+// admin templates compiled into the binary, file names under src/html_tpl
+const EMBEDDED: [(&str, &str); 7] = [
+    ("base.html", include_str!("html_tpl/base.html")),
+    ("admin.html", include_str!("html_tpl/admin.html")),
+    ("admin_find.html", include_str!("html_tpl/admin_find.html")),
+    (
+        "admin_explain.html",
+        include_str!("html_tpl/admin_explain.html"),
+    ),
+    (
+        "admin_store.html",
+        include_str!("html_tpl/admin_store.html"),
+    ),
+    (
+        "admin_store_objects.html",
+        include_str!("html_tpl/admin_store_objects.html"),
+    ),
+    (
+        "admin_store_objects_detailed.html",
+        include_str!("html_tpl/admin_store_objects_detailed.html"),
+    ),
+];
+
+static TEMPLATES: OnceLock<Templates> = OnceLock::new();
+
+struct Templates {
+    tera: ArcSwap<Tera>,
+    dev: Option<DevReload>,
+}
+
+// dev mode: templates are read from dir and parsed again once any of them changes
+struct DevReload {
+    dir: PathBuf,
+    last_modified: Mutex<SystemTime>,
+}
+
+// parses all templates once, call at startup; dev_dir switches to reloading from disk
+pub fn init(dev_dir: Option<&str>) -> tera::Result<()> {
+    let templates = match dev_dir {
+        Some(dir) => {
+            let dev = DevReload {
+                dir: PathBuf::from(dir),
+                last_modified: Mutex::new(SystemTime::UNIX_EPOCH),
+            };
+            *dev.last_modified.lock().unwrap() = dev.modified();
+            log::info!("admin templates are loaded from {}", dir);
+            Templates {
+                tera: ArcSwap::from_pointee(dev.load()?),
+                dev: Some(dev),
+            }
+        }
+        None => Templates {
+            tera: ArcSwap::from_pointee(load_embedded()?),
+            dev: None,
+        },
+    };
+    if TEMPLATES.set(templates).is_err() {
+        log::warn!("templates are already initialized");
+    }
+    Ok(())
+}
+
+pub fn render(name: &str, context: &Context) -> tera::Result<String> {
+    let templates = TEMPLATES
+        .get()
+        .ok_or_else(|| tera::Error::msg("templates are not initialized"))?;
+    if let Some(dev) = templates.dev.as_ref() {
+        dev.reload_if_changed(&templates.tera)?;
+    }
+    templates.tera.load().render(name, context)
+}
+
+fn load_embedded() -> tera::Result<Tera> {
+    let mut tera = Tera::default();
+    tera.add_raw_templates(EMBEDDED)?;
+    Ok(tera)
+}
+
+impl DevReload {
+    fn load(&self) -> tera::Result<Tera> {
+        let mut tera = Tera::default();
+        let files = EMBEDDED
+            .iter()
+            .map(|(name, _)| (self.dir.join(name), Some(*name)))
+            .collect::<Vec<_>>();
+        tera.add_template_files(files)?;
+        Ok(tera)
+    }
+
+    // latest mtime over the template files
+    fn modified(&self) -> SystemTime {
+        EMBEDDED
+            .iter()
+            .filter_map(|(name, _)| std::fs::metadata(self.dir.join(name)).ok())
+            .filter_map(|meta| meta.modified().ok())
+            .max()
+            .unwrap_or(SystemTime::UNIX_EPOCH)
+    }
+
+    fn reload_if_changed(&self, tera: &ArcSwap<Tera>) -> tera::Result<()> {
+        let mut last_modified = self.last_modified.lock().unwrap();
+        let modified = self.modified();
+        if modified <= *last_modified {
+            return Ok(());
+        }
+        log::info!("admin templates changed, reloading");
+        tera.store(self.load()?.into());
+        *last_modified = modified;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_embedded_templates() {
+        let tera = load_embedded().unwrap();
+        let mut context = Context::new();
+        context.insert("object_type", "campaign");
+        context.insert("fields", &["id"]);
+        context.insert("filters", &Vec::<String>::new());
+        context.insert("query", &crate::data::query::ObjectQuery::default());
+        context.insert(
+            "result",
+            &serde_json::json!({"total": 2, "ids": [1, 2], "objects": [{"id": 1}, {"id": 2}]}),
+        );
+        context.insert("prev_url", &None::<String>);
+        context.insert("next_url", &None::<String>);
+        let html = tera.render("admin_store_objects.html", &context).unwrap();
+        assert!(html.contains("<title>admin/store/campaign</title>"));
+        assert!(html.contains(r#"<a href="/admin/store/campaign/2">2</a>"#));
+    }
+}
@@ -1,30 +1,126 @@
-use crate::data::store::Store;
-use crate::handlers::{admin, search};
+use crate::engine::{EngineStat, StorePtr};
+use crate::handlers::{admin, admin_api, metrics, search};
+use crate::reload::{AdminAction, ServerRequest};
+use crate::router::{HttpError, RouteMatch, Router};
 use crate::task::{AdminTask, HttpTask, SearchTask};
 use crate::{config, helpers};
-use crossbeam_channel::Receiver;
-use std::ops::Deref;
-use std::sync::atomic::AtomicBool;
-use std::sync::{Arc, RwLock};
+use crossbeam_channel::{Receiver, Sender};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread;
 use std::thread::JoinHandle;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use tiny_http::Method;
 
 pub type ControlTask = Box<dyn FnOnce(&mut WorkerData) + Send + 'static>;
 
+#[derive(Clone, Copy)]
+enum Endpoint {
+    Search,
+    ConfigReload,
+    Action(AdminAction),
+    Metrics,
+    Admin(admin::Handler),
+    AdminApi(admin_api::Handler),
+}
+
+impl Endpoint {
+    fn is_admin(&self) -> bool {
+        !matches!(self, Endpoint::Search)
+    }
+}
+
+fn build_router() -> Router<Endpoint> {
+    let router = Router::new()
+        .add(&[Method::Get], "/search", Endpoint::Search)
+        .add(&[Method::Get], "/metrics", Endpoint::Metrics)
+        .add(
+            &[Method::Post],
+            "/admin/config/reload",
+            Endpoint::ConfigReload,
+        )
+        .add(
+            &[Method::Post],
+            "/admin/actions/swap",
+            Endpoint::Action(AdminAction::Swap),
+        )
+        .add(
+            &[Method::Post],
+            "/admin/actions/full-reload",
+            Endpoint::Action(AdminAction::FullReload),
+        )
+        .add(
+            &[Method::Post],
+            "/admin/actions/check",
+            Endpoint::Action(AdminAction::Check { with_db: false }),
+        )
+        .add(
+            &[Method::Post],
+            "/admin/actions/check-db",
+            Endpoint::Action(AdminAction::Check { with_db: true }),
+        );
+    let router = admin::routes()
+        .into_iter()
+        .fold(router, |router, (pattern, handler)| {
+            router.add(&[Method::Get], pattern, Endpoint::Admin(handler))
+        });
+    admin_api::routes()
+        .into_iter()
+        .fold(router, |router, (pattern, handler)| {
+            router.add(&[Method::Get], pattern, Endpoint::AdminApi(handler))
+        })
+}
+
+// route patterns in router order, used as metrics labels
+pub fn route_names() -> Vec<&'static str> {
+    build_router().patterns()
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum WorkerRole {
+    Mixed,
+    Search,
+    Admin,
+}
+
 pub struct WorkerData {
     pub num: i32,
+    pub role: WorkerRole,
     pub task_queue: Receiver<HttpTask>,
     pub ctl_task_queue: Receiver<ControlTask>,
-    pub store: Arc<RwLock<Store>>,
+    pub store: StorePtr,
     pub config: config::Worker,
+    pub server_queue: Sender<ServerRequest>,
+    pub engine_stat: Arc<EngineStat>,
 }
 
-pub fn run(worker_data: WorkerData, shutdown: Arc<AtomicBool>) -> JoinHandle<()> {
+impl WorkerData {
+    fn task_taken(&self) {
+        let queue = match self.role {
+            WorkerRole::Admin => "admin",
+            _ => "search",
+        };
+        if let Some(depth) = self.engine_stat.task_queue_depth(queue) {
+            depth.pop();
+        }
+    }
+
+    fn ctl_task_taken(&self) {
+        if let Some(depth) = self.engine_stat.ctl_queues.get(self.num as usize) {
+            depth.pop();
+        }
+    }
+}
+
+pub fn run(worker_data: WorkerData, core: usize, shutdown: Arc<AtomicBool>) -> JoinHandle<()> {
     thread::Builder::new()
-        .name(format!("worker_{}", worker_data.num))
+        .name(match worker_data.role {
+            WorkerRole::Admin => format!("admin_worker_{}", worker_data.num),
+            _ => format!("worker_{}", worker_data.num),
+        })
         .spawn(move || {
-            helpers::bind_thread((worker_data.num + 2) as usize);
+            helpers::bind_thread(core);
             worker_loop(worker_data, shutdown)
         })
         .unwrap()
@@ -32,22 +128,40 @@ pub fn run(worker_data: WorkerData, shutdown: Arc<AtomicBool>) -> JoinHandle<()>
 
 fn worker_loop(mut worker_data: WorkerData, shutdown: Arc<AtomicBool>) {
     let mut stop_checker = helpers::StopChecker::new(shutdown);
+    let router = build_router();
+    let (task_queue, ctl_task_queue) = (
+        worker_data.task_queue.clone(),
+        worker_data.ctl_task_queue.clone(),
+    );
     loop {
-        if let Ok(ctl_task) = worker_data.ctl_task_queue.try_recv() {
+        // ctl tasks go first: config updates should not wait behind the request queue
+        while let Ok(ctl_task) = ctl_task_queue.try_recv() {
+            worker_data.ctl_task_taken();
             ctl_task(&mut worker_data)
         }
-        match worker_data
-            .task_queue
-            .recv_timeout(Duration::from_millis(50))
-        {
-            Ok(req) => {
-                process(&worker_data, req);
-            }
-            Err(_) => {
+        crossbeam_channel::select! {
+            recv(task_queue) -> req => match req {
+                Ok(req) => {
+                    worker_data.task_taken();
+                    process(&worker_data, &router, req)
+                }
+                Err(_) => {
+                    if stop_checker.is_time_force() {
+                        break;
+                    }
+                }
+            },
+            recv(ctl_task_queue) -> ctl_task => {
+                if let Ok(ctl_task) = ctl_task {
+                    worker_data.ctl_task_taken();
+                    ctl_task(&mut worker_data)
+                }
+            },
+            default(Duration::from_millis(50)) => {
                 if stop_checker.is_time_force() {
                     break;
                 }
-            }
+            },
         }
         if stop_checker.is_time() {
             break;
@@ -56,25 +170,142 @@ fn worker_loop(mut worker_data: WorkerData, shutdown: Arc<AtomicBool>) {
     log::info!("worker {} stopped", worker_data.num)
 }
 
-fn process(worker_data: &WorkerData, http_task: HttpTask) {
+fn process(worker_data: &WorkerData, router: &Router<Endpoint>, http_task: HttpTask) {
     log::trace!(
         "worker {} got request: {}",
         worker_data.num,
         http_task.url()
     );
-    let store_r = worker_data.store.read().unwrap();
+    if http_task.is_expired() {
+        worker_data
+            .engine_stat
+            .expired_requests
+            .fetch_add(1, Ordering::Relaxed);
+        http_task.respond_unavailable("request expired in queue", None);
+        return;
+    }
+
+    let start = Instant::now();
+    let (endpoint, params) = match router.find(http_task.method(), http_task.url()) {
+        RouteMatch::Found(endpoint, params) => (*endpoint, params),
+        RouteMatch::MethodNotAllowed(allowed) => {
+            http_task.respond_method_not_allowed(&allowed);
+            return;
+        }
+        RouteMatch::NotFound => {
+            let err = HttpError::not_found(format!("unknown url: {}", http_task.url()).as_str());
+            http_task.respond_error(&err);
+            return;
+        }
+    };
+
+    // with a dedicated admin listener each side only serves its own urls
+    if matches!(
+        (worker_data.role, endpoint.is_admin()),
+        (WorkerRole::Search, true) | (WorkerRole::Admin, false)
+    ) {
+        let err = HttpError::not_found(format!("unknown url: {}", http_task.url()).as_str());
+        http_task.respond_error(&err);
+        return;
+    }
+
+    let worker_num = worker_data.num as usize;
+    let engine_stat = worker_data.engine_stat.as_ref();
+    // don't pin the store snapshot while waiting for the main thread, a swap would wait for us
+    match endpoint {
+        Endpoint::ConfigReload => {
+            admin::handle_config_reload(http_task, &worker_data.server_queue);
+            engine_stat.observe_request(worker_num, params.route(), start.elapsed());
+            return;
+        }
+        Endpoint::Action(action) => {
+            admin::handle_action(http_task, action, &worker_data.server_queue);
+            engine_stat.observe_request(worker_num, params.route(), start.elapsed());
+            return;
+        }
+        _ => {}
+    }
+
+    let store = worker_data.store.load();
+    let worker_stat = &engine_stat.workers[worker_num];
+    worker_stat
+        .store_iteration
+        .store(store.get_store_stat().iteration, Ordering::Release);
+    worker_stat.busy.store(true, Ordering::Release);
 
-    let req_url = http_task.url();
-    let store_ref = store_r.deref();
+    let req_url = http_task.url().to_string();
+    let store_ref = store.as_ref();
     let config = &worker_data.config;
 
-    if req_url.starts_with("/admin") {
-        let task = AdminTask::new(http_task, store_ref, config);
-        admin::handle(task);
-    } else if req_url.starts_with("/search") {
-        let task = SearchTask::new(http_task, store_ref, config);
-        search::handle(task);
-    } else {
-        http_task.respond_html("unknown method")
+    // a panicking handler must not take the worker down; the dropped request answers 500
+    let result = panic::catch_unwind(AssertUnwindSafe(|| match endpoint {
+        Endpoint::Admin(handler) => {
+            let task = AdminTask::new(http_task, store_ref, config, engine_stat);
+            admin::handle(task, handler, &params);
+        }
+        Endpoint::AdminApi(handler) => {
+            let task = AdminTask::new(http_task, store_ref, config, engine_stat);
+            admin_api::handle(task, handler, &params);
+        }
+        Endpoint::Search => {
+            let task = SearchTask::new(http_task, store_ref, config, engine_stat);
+            search::handle(task);
+        }
+        Endpoint::Metrics => {
+            let task = AdminTask::new(http_task, store_ref, config, engine_stat);
+            metrics::handle(task);
+        }
+        Endpoint::ConfigReload | Endpoint::Action(_) => unreachable!(),
+    }));
+    if result.is_err() {
+        log::error!(
+            "worker {} panicked on request: {}",
+            worker_data.num,
+            req_url
+        );
+    }
+    worker_stat.busy.store(false, Ordering::Release);
+    engine_stat.observe_request(worker_num, params.route(), start.elapsed());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::store::Store;
+    use crate::engine::EngineStat;
+    use crate::task::test::http_request;
+    use arc_swap::ArcSwap;
+
+    #[test]
+    fn test_expired_task_is_dropped() {
+        let (_task_snd, task_queue) = crossbeam_channel::unbounded();
+        let (_ctl_snd, ctl_task_queue) = crossbeam_channel::unbounded();
+        let (server_queue, _server_rcv) = crossbeam_channel::unbounded();
+        let worker_data = WorkerData {
+            num: 0,
+            role: WorkerRole::Mixed,
+            task_queue,
+            ctl_task_queue,
+            store: Arc::new(ArcSwap::from_pointee(Store::default())),
+            config: config::Worker::default(),
+            server_queue,
+            engine_stat: Arc::new(EngineStat::default()),
+        };
+
+        let (_server, req, client) = http_request("/search?id=1");
+        let task = HttpTask::new(req, Some(Duration::from_millis(1)));
+        thread::sleep(Duration::from_millis(5));
+        process(&worker_data, &build_router(), task);
+
+        let response = client.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 503"));
+        assert!(response.ends_with("request expired in queue"));
+        let stat = &worker_data.engine_stat;
+        assert_eq!(1, stat.expired_requests.load(Ordering::Relaxed));
+
+        // changes server state, so a link or a prefetch can't trigger it
+        let (_server, req, client) = http_request("/admin/config/reload");
+        process(&worker_data, &build_router(), HttpTask::new(req, None));
+        assert!(client.join().unwrap().starts_with("HTTP/1.1 405"));
     }
 }